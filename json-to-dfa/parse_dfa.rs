@@ -7,7 +7,9 @@
 
 use std::path::PathBuf;
 use std::fs;
+use std::process::exit;
 use clap::Parser;
+use monitor::MonitorError;
 
 /// Utility that parses an input JSON file into a serialized Rust DFA object, optionally copying it over to a
 /// specified output directory
@@ -20,20 +22,32 @@ struct Args {
     /// Optional output directory
     #[arg(short, long, value_name = "DIR")]
     output_dir: Option<PathBuf>,
+
+    /// Minimize the DFA before serializing it
+    #[arg(short, long)]
+    minimize: bool,
 }
 
 fn main() {
     let args = Args::parse();
-    let dfa = monitor::Dfa::deserialize_from_json(args.input_file);
-    let saved_path = dfa.serialize();
-
-    if let Some(out_dir) = args.output_dir {
-        assert!(fs::metadata(&out_dir).unwrap().is_dir(), "Specified output file is not a directory");
-        let dfa_saved_file_name = &saved_path.file_name().unwrap();
-        let new_file = out_dir.join(dfa_saved_file_name);
-        fs::copy(saved_path, &new_file).expect("Copying serialized DFA to target directory failed");
-        println!("{:?}", new_file);
-    } else {
-        println!("{:?}", saved_path);
+    match parse(args) {
+        Ok(saved_path) => println!("{:?}", saved_path),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(e.exit_code());
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Serializes the DFA as asked, returning where it ended up
+fn parse(args: Args) -> Result<PathBuf, MonitorError> {
+    let dfa = monitor::Dfa::deserialize_from_json(args.input_file)?;
+    let dfa = if args.minimize { dfa.minimize() } else { dfa };
+    let saved_path = dfa.serialize()?;
+
+    let Some(out_dir) = args.output_dir else { return Ok(saved_path) };
+    if !out_dir.is_dir() { return Err(MonitorError::Usage(format!("Specified output directory {:?} is not a directory", out_dir))) }
+    let new_file = out_dir.join(saved_path.file_name().expect("Serialized DFAs are files"));
+    fs::copy(saved_path, &new_file)?;
+    Ok(new_file)
+}
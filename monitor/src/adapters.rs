@@ -0,0 +1,151 @@
+use std::io::{self, BufRead, Read, Write};
+use regex_automata::dfa::Automaton;
+use crate::{Framing, LineVerdict, MonitorError, StreamValidator, ValidationFailure};
+
+/// Bytes the validating readers read from their inner stream at a time
+pub(crate) const CHUNK_LEN: usize = 8 * 1024;
+
+/// Writer that validates what's written to it line by line, passing each line on to the inner writer unchanged once
+/// it has validated. A line that fails is never passed on: the write fails with an `io::ErrorKind::InvalidData` error
+/// wrapping a `MonitorError::Validation` (see `MonitorError::from`), and so does every write after it. Call `finish`
+/// once done, to validate the last line if it wasn't delimited - it's held back (and never validated) otherwise.
+pub struct ValidatingWriter<W: Write, A: Automaton> {
+    inner: W,
+    validator: StreamValidator<A>,
+    failed: Option<(usize, ValidationFailure)>,
+}
+impl<W: Write, A: Automaton> ValidatingWriter<W, A> {
+    /// Writer of newline delimited lines
+    pub fn new(inner: W, dfa: A) -> Result<Self, MonitorError> { Self::with_framing(inner, dfa, Framing::default()) }
+    pub fn with_framing(inner: W, dfa: A, framing: Framing) -> Result<Self, MonitorError> {
+        Ok(ValidatingWriter { inner, validator: StreamValidator::with_framing(dfa, framing)?, failed: None })
+    }
+    pub fn get_ref(&self) -> &W { &self.inner }
+    /// Validates and writes out the last line if it wasn't delimited (or fails the stream if nothing was written and
+    /// the DFA doesn't match the empty string), flushing the inner writer and handing it back
+    pub fn finish(mut self) -> io::Result<W> {
+        self.check()?;
+        let verdicts = self.validator.finish().map_err(io::Error::other)?;
+        self.pass(verdicts, false)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+    fn check(&self) -> io::Result<()> {
+        match &self.failed { Some((line_no, failure)) => Err(validation_error(*line_no, failure.clone())), None => Ok(()) }
+    }
+    /// Writes out the valid lines, up to the first invalid one
+    fn pass(&mut self, verdicts: Vec<LineVerdict>, delimited: bool) -> io::Result<()> {
+        for verdict in verdicts {
+            match verdict {
+                LineVerdict::Valid(mut line, _, cr) => {
+                    self.validator.framing().restore(&mut line, cr, delimited);
+                    self.inner.write_all(&line)?;
+                },
+                LineVerdict::Invalid(line_no, failure) => {
+                    self.failed = Some((line_no, failure));
+                    return self.check()
+                },
+            }
+        }
+        Ok(())
+    }
+}
+impl<W: Write, A: Automaton> Write for ValidatingWriter<W, A> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check()?;
+        let verdicts = self.validator.feed(buf).map_err(io::Error::other)?;
+        self.pass(verdicts, true)?;
+        Ok(buf.len())
+    }
+    /// Flushes the inner writer - a line still being written stays held back until it's complete
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+/// Reader that validates what it reads from the inner reader line by line, only handing lines out (unchanged) once
+/// they have validated. Reading past the lines before a failing one fails with an `io::ErrorKind::InvalidData` error wrapping
+/// a `MonitorError::Validation` (see `MonitorError::from`), as does every read after it.
+pub struct ValidatingReader<R: Read, A: Automaton> {
+    inner: R,
+    validation: ReadValidation<A>,
+}
+impl<R: Read, A: Automaton> ValidatingReader<R, A> {
+    /// Reader of newline delimited lines
+    pub fn new(inner: R, dfa: A) -> Result<Self, MonitorError> { Self::with_framing(inner, dfa, Framing::default()) }
+    pub fn with_framing(inner: R, dfa: A, framing: Framing) -> Result<Self, MonitorError> {
+        Ok(ValidatingReader { inner, validation: ReadValidation::new(dfa, framing)? })
+    }
+    pub fn get_ref(&self) -> &R { &self.inner }
+    pub fn into_inner(self) -> R { self.inner }
+}
+impl<R: Read, A: Automaton> BufRead for ValidatingReader<R, A> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.validation.needs_input()? {
+            let read = match self.inner.read(self.validation.chunk()) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.validation.validate(read)?;
+        }
+        Ok(self.validation.validated())
+    }
+    fn consume(&mut self, amt: usize) { self.validation.consume(amt); }
+}
+impl<R: Read, A: Automaton> Read for ValidatingReader<R, A> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+/// The validating readers' state: chunks read from the inner reader go through the validator, and the lines that
+/// validate wait in a buffer until they're read
+pub(crate) struct ReadValidation<A: Automaton> {
+    validator: StreamValidator<A>,
+    chunk: Vec<u8>,
+    validated: Vec<u8>, //Validated lines not yet read, from pos on
+    pos: usize,
+    failed: Option<(usize, ValidationFailure)>,
+    eof: bool,
+}
+impl<A: Automaton> ReadValidation<A> {
+    pub(crate) fn new(dfa: A, framing: Framing) -> Result<Self, MonitorError> {
+        let validator = StreamValidator::with_framing(dfa, framing)?;
+        Ok(ReadValidation { validator, chunk: vec![0; CHUNK_LEN], validated: Vec::new(), pos: 0, failed: None, eof: false })
+    }
+    /// Whether another chunk has to be read before anything can be handed out - failing once every line before the
+    /// failing one has been
+    pub(crate) fn needs_input(&self) -> io::Result<bool> {
+        if self.pos < self.validated.len() { return Ok(false) }
+        if let Some((line_no, failure)) = &self.failed { return Err(validation_error(*line_no, failure.clone())) }
+        Ok(!self.eof)
+    }
+    /// Buffer to read the next chunk into
+    pub(crate) fn chunk(&mut self) -> &mut [u8] { &mut self.chunk }
+    /// Validates the first `read` bytes of the chunk (the end of the stream if none)
+    pub(crate) fn validate(&mut self, read: usize) -> io::Result<()> {
+        self.eof = read == 0;
+        let verdicts = if self.eof { self.validator.finish() } else { self.validator.feed(&self.chunk[..read]) }.map_err(io::Error::other)?;
+        self.validated.clear();
+        self.pos = 0;
+        for verdict in verdicts {
+            match verdict {
+                LineVerdict::Valid(mut line, _, cr) => {
+                    self.validator.framing().restore(&mut line, cr, !self.eof);
+                    self.validated.extend_from_slice(&line);
+                },
+                LineVerdict::Invalid(line_no, failure) => { self.failed = Some((line_no, failure)); break },
+            }
+        }
+        Ok(())
+    }
+    pub(crate) fn validated(&self) -> &[u8] { &self.validated[self.pos..] }
+    pub(crate) fn consume(&mut self, amt: usize) { self.pos = (self.pos + amt).min(self.validated.len()); }
+}
+
+pub(crate) fn validation_error(line_no: usize, failure: ValidationFailure) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, MonitorError::Validation(vec![(line_no, failure)]))
+}
@@ -0,0 +1,98 @@
+use std::{collections::VecDeque, io, pin::Pin, task::{ready, Context, Poll}};
+use bytes::Bytes;
+use futures_core::Stream;
+use regex_automata::dfa::Automaton;
+use tokio::io::{AsyncBufRead, AsyncRead, ReadBuf};
+use crate::{adapters::{ReadValidation, CHUNK_LEN}, Framing, LineVerdict, MonitorError, StreamValidator};
+
+/// `ValidatingReader` for tokio: reads from the inner reader line by line, only handing lines out once they have
+/// validated, and failing with an `io::ErrorKind::InvalidData` error wrapping a `MonitorError::Validation` once the
+/// lines before a failing one have been read
+pub struct AsyncValidatingReader<R: AsyncRead + Unpin, A: Automaton + Unpin> {
+    inner: R,
+    validation: ReadValidation<A>,
+}
+impl<R: AsyncRead + Unpin, A: Automaton + Unpin> AsyncValidatingReader<R, A> {
+    /// Reader of newline delimited lines
+    pub fn new(inner: R, dfa: A) -> Result<Self, MonitorError> { Self::with_framing(inner, dfa, Framing::default()) }
+    pub fn with_framing(inner: R, dfa: A, framing: Framing) -> Result<Self, MonitorError> {
+        Ok(AsyncValidatingReader { inner, validation: ReadValidation::new(dfa, framing)? })
+    }
+    pub fn get_ref(&self) -> &R { &self.inner }
+    pub fn into_inner(self) -> R { self.inner }
+}
+impl<R: AsyncRead + Unpin, A: Automaton + Unpin> AsyncBufRead for AsyncValidatingReader<R, A> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        while this.validation.needs_input()? {
+            let mut buf = ReadBuf::new(this.validation.chunk());
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut buf))?;
+            let read = buf.filled().len();
+            this.validation.validate(read)?;
+        }
+        Poll::Ready(Ok(this.validation.validated()))
+    }
+    fn consume(self: Pin<&mut Self>, amt: usize) { self.get_mut().validation.consume(amt); }
+}
+impl<R: AsyncRead + Unpin, A: Automaton + Unpin> AsyncRead for AsyncValidatingReader<R, A> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let available = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let len = available.len().min(buf.remaining());
+        buf.put_slice(&available[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Stream of the validated lines (less their delimiter and any \r handled by the framing) read from an async reader.
+/// A failing line ends the stream with a `MonitorError::Validation` (and an I/O error with `MonitorError::Io`).
+pub struct ValidatedLines<R: AsyncRead + Unpin, A: Automaton + Unpin> {
+    inner: R,
+    validator: StreamValidator<A>,
+    chunk: Vec<u8>,
+    lines: VecDeque<Bytes>, //Validated lines not yet yielded
+    error: Option<MonitorError>, //What ends the stream once the lines before it have been yielded
+    done: bool,
+}
+impl<R: AsyncRead + Unpin, A: Automaton + Unpin> ValidatedLines<R, A> {
+    /// Stream of newline delimited lines
+    pub fn new(inner: R, dfa: A) -> Result<Self, MonitorError> { Self::with_framing(inner, dfa, Framing::default()) }
+    pub fn with_framing(inner: R, dfa: A, framing: Framing) -> Result<Self, MonitorError> {
+        let validator = StreamValidator::with_framing(dfa, framing)?;
+        Ok(ValidatedLines { inner, validator, chunk: vec![0; CHUNK_LEN], lines: VecDeque::new(), error: None, done: false })
+    }
+    /// Reads and validates the next chunk (or ends the stream if there are none left), queueing up what it yields
+    fn poll_chunk(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut buf = ReadBuf::new(&mut self.chunk);
+        let verdicts = match ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf)) {
+            Ok(()) if buf.filled().is_empty() => { self.done = true; self.validator.finish() },
+            Ok(()) => { let read = buf.filled().len(); self.validator.feed(&self.chunk[..read]) },
+            Err(e) => Err(MonitorError::Io(e)),
+        };
+        match verdicts {
+            Ok(verdicts) => for verdict in verdicts {
+                match verdict {
+                    LineVerdict::Valid(line, _, _) => self.lines.push_back(Bytes::from(line)),
+                    LineVerdict::Invalid(line_no, failure) => { self.error = Some(MonitorError::Validation(vec![(line_no, failure)])); break },
+                }
+            },
+            Err(e) => self.error = Some(e),
+        }
+        Poll::Ready(())
+    }
+}
+impl<R: AsyncRead + Unpin, A: Automaton + Unpin> Stream for ValidatedLines<R, A> {
+    type Item = Result<Bytes, MonitorError>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(line) = this.lines.pop_front() { return Poll::Ready(Some(Ok(line))) }
+            if let Some(e) = this.error.take() {
+                this.done = true;
+                return Poll::Ready(Some(Err(e)))
+            }
+            if this.done { return Poll::Ready(None) }
+            ready!(this.poll_chunk(cx));
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::{HashMap, VecDeque};
+use regex_automata::{dfa::Automaton, util::primitives::StateID};
+use crate::{Dfa, TransitionDesc, TransitionTable};
+
+/// Complete DFA over bytes with one full row of targets per state - the working form for constructing and
+/// transforming DFAs. State 0 is a dead state.
+pub(crate) struct ByteDfa {
+    pub(crate) start: usize,
+    pub(crate) accept: Vec<bool>,
+    pub(crate) next: Vec<[usize; 256]>,
+}
+impl ByteDfa {
+    /// Expands a `Dfa`'s dense table, keeping its row order (so its dead state stays at 0)
+    pub(crate) fn from_dfa(dfa: &Dfa) -> Self {
+        let len = dfa.state_len();
+        let row = |id: StateID| dfa.dense.row(id);
+        let next = (0..len)
+            .map(|state| std::array::from_fn(|byte| row(dfa.next_state(dfa.dense.id_of_row(state), byte as u8))))
+            .collect();
+        let accept = (0..len).map(|state| dfa.is_match_state(dfa.dense.id_of_row(state))).collect();
+        ByteDfa { start: row(dfa.dense.start()), accept, next }
+    }
+    pub(crate) fn len(&self) -> usize { self.accept.len() }
+    /// Product construction, accepting wherever `accept` does given whether each DFA accepts. Pairs of dead states
+    /// become the dead state, so `accept(false, false)` must be false.
+    pub(crate) fn product(&self, other: &ByteDfa, accept: fn(bool, bool) -> bool) -> Self {
+        let mut dfa = ByteDfa { start: 1, accept: vec![false], next: vec![[0; 256]] };
+        let mut ids = HashMap::from([((0, 0), 0)]);
+        let mut queue = VecDeque::new();
+        let mut state_of = |pair: (usize, usize), dfa: &mut ByteDfa, queue: &mut VecDeque<(usize, usize)>| {
+            *ids.entry(pair).or_insert_with(|| {
+                dfa.accept.push(accept(self.accept[pair.0], other.accept[pair.1]));
+                dfa.next.push([0; 256]);
+                queue.push_back(pair);
+                dfa.len() - 1
+            })
+        };
+        dfa.start = state_of((self.start, other.start), &mut dfa, &mut queue);
+        while let Some((a, b)) = queue.pop_front() {
+            let id = state_of((a, b), &mut dfa, &mut queue);
+            for byte in 0..256 {
+                dfa.next[id][byte] = state_of((self.next[a][byte], other.next[b][byte]), &mut dfa, &mut queue);
+            }
+        }
+        dfa
+    }
+    pub(crate) fn intersect(&self, other: &ByteDfa) -> Self { self.product(other, |a, b| a && b) }
+    pub(crate) fn union(&self, other: &ByteDfa) -> Self { self.product(other, |a, b| a || b) }
+    pub(crate) fn difference(&self, other: &ByteDfa) -> Self { self.product(other, |a, b| a && !b) }
+    /// Strings accepted by exactly one of the DFAs
+    pub(crate) fn symmetric_difference(&self, other: &ByteDfa) -> Self { self.product(other, |a, b| a != b) }
+    /// Complement over every string of bytes - the dead state becomes an accepting sink
+    pub(crate) fn complement(&self) -> Self {
+        let accept = self.accept.iter().map(|accept| !accept).collect();
+        ByteDfa { start: self.start, accept, next: self.next.clone() }.minimize()
+    }
+    /// Shortest accepted string (the lexicographically smallest of them, if several), found breadth first
+    pub(crate) fn shortest_match(&self) -> Option<Vec<u8>> {
+        let mut parents: Vec<Option<(usize, u8)>> = vec![None; self.len()];
+        let mut seen = vec![false; self.len()];
+        let mut queue = VecDeque::from([self.start]);
+        seen[self.start] = true;
+        while let Some(state) = queue.pop_front() {
+            if self.accept[state] {
+                let mut line = Vec::new();
+                let mut at = state;
+                while let Some((parent, byte)) = parents[at] { line.push(byte); at = parent; }
+                line.reverse();
+                return Some(line)
+            }
+            for (byte, next) in self.next[state].iter().enumerate() {
+                if !seen[*next] { seen[*next] = true; parents[*next] = Some((state, byte as u8)); queue.push_back(*next); }
+            }
+        }
+        None
+    }
+    /// Hopcroft's partition refinement over byte ranges (runs of bytes no state tells apart), after merging every
+    /// state that is unreachable or can't reach a match into the dead state. Survivors are renumbered in breadth
+    /// first order from the start (the dead state staying at 0), so equivalent DFAs come out identical.
+    pub(crate) fn minimize(&self) -> Self {
+        let live = self.live_states();
+        let ranges: Vec<usize> = (0..256)
+            .filter(|byte| *byte == 0 || self.next.iter().any(|row| row[*byte] != row[byte - 1]))
+            .collect();
+        //Initial partition: dead, live matching and live non-matching states
+        let mut block = vec![0; self.len()];
+        let mut members: Vec<Vec<usize>> = Vec::new();
+        let mut kinds = HashMap::new();
+        for state in 0..self.len() {
+            let kind = if !live[state] { 0 } else if self.accept[state] { 1 } else { 2 };
+            block[state] = *kinds.entry(kind).or_insert_with(|| { members.push(Vec::new()); members.len() - 1 });
+            members[block[state]].push(state);
+        }
+        let mut preds = vec![vec![Vec::new(); self.len()]; ranges.len()];
+        for (state, row) in self.next.iter().enumerate() {
+            for (range, byte) in ranges.iter().enumerate() { preds[range][row[*byte]].push(state); }
+        }
+        let mut work: Vec<(usize, usize)> = (0..members.len()).flat_map(|b| (0..ranges.len()).map(move |r| (b, r))).collect();
+        let mut in_work = vec![vec![true; ranges.len()]; members.len()];
+        let mut marked = vec![false; self.len()];
+        while let Some((splitter, range)) = work.pop() {
+            in_work[splitter][range] = false;
+            //Group the states leading into the splitter on this range by their block
+            let mut touched: HashMap<usize, Vec<usize>> = HashMap::new();
+            for target in &members[splitter] {
+                for pred in &preds[range][*target] { touched.entry(block[*pred]).or_default().push(*pred); }
+            }
+            for (split, states) in touched {
+                if states.len() == members[split].len() { continue }
+                let new = members.len();
+                for state in &states { marked[*state] = true; block[*state] = new; }
+                members[split].retain(|state| !marked[*state]);
+                for state in &states { marked[*state] = false; }
+                members.push(states);
+                in_work.push(vec![false; ranges.len()]);
+                let new_smaller = members[new].len() <= members[split].len();
+                for (range, split_pending) in in_work[split].clone().into_iter().enumerate() {
+                    let smaller = if split_pending || new_smaller { new } else { split };
+                    in_work[smaller][range] = true;
+                    work.push((smaller, range));
+                }
+            }
+        }
+        //Renumber breadth first
+        let mut ids = vec![usize::MAX; members.len()];
+        if let Some(dead) = (0..self.len()).find(|state| !live[*state]) { ids[block[dead]] = 0; }
+        let mut order = Vec::new();
+        let mut queue = VecDeque::new();
+        if live[self.start] { ids[block[self.start]] = 1; order.push(self.start); queue.push_back(self.start); }
+        while let Some(state) = queue.pop_front() {
+            for next in self.next[state] {
+                if ids[block[next]] == usize::MAX {
+                    ids[block[next]] = order.len() + 1;
+                    order.push(next);
+                    queue.push_back(next);
+                }
+            }
+        }
+        let start = if live[self.start] { 1 } else { 0 };
+        let mut dfa = ByteDfa { start, accept: vec![false], next: vec![[0; 256]] };
+        for state in order {
+            dfa.accept.push(self.accept[state]);
+            dfa.next.push(self.next[state].map(|next| ids[block[next]]));
+        }
+        dfa
+    }
+    /// States reachable from the start that can reach a match state
+    fn live_states(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.len()];
+        let mut stack = vec![self.start];
+        reachable[self.start] = true;
+        let mut preds = vec![Vec::new(); self.len()];
+        while let Some(state) = stack.pop() {
+            for next in self.next[state] {
+                preds[next].push(state);
+                if !reachable[next] { reachable[next] = true; stack.push(next); }
+            }
+        }
+        let mut live = vec![false; self.len()];
+        let mut stack: Vec<usize> = (0..self.len()).filter(|s| reachable[*s] && self.accept[*s]).collect();
+        for state in &stack { live[*state] = true; }
+        while let Some(state) = stack.pop() {
+            for pred in &preds[state] {
+                if !live[*pred] { live[*pred] = true; stack.push(*pred); }
+            }
+        }
+        live
+    }
+    /// Converts a minimized DFA into a `Dfa`, whose dead state shares ID 0 with this one
+    pub(crate) fn into_dfa(self) -> Dfa {
+        let mut table = TransitionTable::new();
+        for (state, row) in self.next.iter().enumerate().skip(1) {
+            let trans_descs = row_ranges(row).into_iter()
+                .map(|(lo, hi, next)| if lo == hi { TransitionDesc::Match(lo, StateID::must(next)) } else { TransitionDesc::Range(lo, hi, StateID::must(next)) })
+                .collect();
+            table.insert(StateID::must(state), trans_descs);
+        }
+        let match_states = (0..self.len()).filter(|s| self.accept[*s]).map(StateID::must).collect();
+        //A DFA matching nothing still gets a (transitionless) start state of its own, like the Java builder gave it
+        let start = if self.start == 0 { table.insert(StateID::must(1), Vec::new()); 1 } else { self.start };
+        Dfa::with_dead_state(StateID::must(start), match_states, table)
+    }
+}
+
+/// Maximal byte ranges leading to the same state, leaving out those leading to the dead state
+pub(crate) fn row_ranges(row: &[usize]) -> Vec<(u8, u8, usize)> {
+    let mut ranges: Vec<(u8, u8, usize)> = Vec::new();
+    for (byte, next) in row.iter().enumerate() {
+        if *next == 0 { continue }
+        match ranges.last_mut() {
+            Some((_, hi, last)) if *last == *next && *hi as usize + 1 == byte => *hi = byte as u8,
+            _ => ranges.push((byte as u8, byte as u8, *next)),
+        }
+    }
+    ranges
+}
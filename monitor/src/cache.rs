@@ -0,0 +1,128 @@
+use std::{
+    env,
+    fs::{self, create_dir_all},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use crate::{write_bytes, SerDfa, HEADER_LEN, MAGIC};
+
+/// Environment variable that overrides where the DFA cache lives
+pub const CACHE_DIR_VAR: &str = "STREAMONITOR_CACHE_DIR";
+/// Cache directory used in development builds when there is neither a cache directory variable nor a home directory
+const DEV_CACHE_DIR: &str = "serialized-dfa-cache";
+
+/// Directory of serialized DFAs, each named after the hash of its canonical form (so the same DFA is only ever stored
+/// once) and optionally accompanied by a `.source` file saying what it was built from
+pub struct Cache {
+    dir: PathBuf,
+}
+/// A serialized DFA in the cache
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub path: PathBuf,
+    /// Size on disk in bytes, source file included
+    pub size: u64,
+    /// When the DFA was last written to the cache
+    pub modified: SystemTime,
+    /// What the DFA was built from (eg. its regular expression), if recorded
+    pub source: Option<String>,
+}
+
+impl Cache {
+    /// The cache the monitor uses: `$STREAMONITOR_CACHE_DIR` if set, otherwise `streamonitor` under the XDG cache
+    /// directory (`$XDG_CACHE_HOME`, or `~/.cache`). Without a home directory either, development builds fall back to
+    /// the crate's own `serialized-dfa-cache` and release builds to the system temporary directory.
+    pub fn locate() -> Self {
+        let var = |name: &str| env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+        let dir = var(CACHE_DIR_VAR)
+            .or_else(|| var("XDG_CACHE_HOME").filter(|dir| dir.is_absolute()).map(|dir| dir.join("streamonitor")))
+            .or_else(|| var("HOME").map(|home| home.join(".cache").join("streamonitor")))
+            .unwrap_or_else(|| if cfg!(debug_assertions) { PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(DEV_CACHE_DIR) } else { env::temp_dir().join("streamonitor") });
+        Cache { dir }
+    }
+    /// Cache kept in the given directory
+    pub fn at(dir: PathBuf) -> Self { Cache { dir } }
+    pub fn dir(&self) -> &Path { &self.dir }
+    /// Every DFA in the cache, least recently written first (none if the directory doesn't exist yet)
+    pub fn entries(&self) -> io::Result<Vec<CacheEntry>> {
+        let listing = match fs::read_dir(&self.dir) {
+            Ok(listing) => listing,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut entries = Vec::new();
+        for file in listing {
+            let path = file?.path();
+            if !is_entry_name(&path) { continue }
+            let metadata = fs::metadata(&path)?;
+            let source_path = path.with_extension("source");
+            let source = fs::read_to_string(&source_path).ok();
+            let source_size = fs::metadata(&source_path).map_or(0, |metadata| metadata.len());
+            entries.push(CacheEntry { size: metadata.len() + source_size, modified: metadata.modified()?, source, path });
+        }
+        entries.sort_by(|a, b| (a.modified, &a.path).cmp(&(b.modified, &b.path)));
+        Ok(entries)
+    }
+    /// Removes the entries last written longer than `older_than` ago, then the least recently written until the
+    /// cache takes up at most `max_size` bytes, returning the entries removed
+    pub fn gc(&self, older_than: Option<Duration>, max_size: Option<u64>) -> io::Result<Vec<CacheEntry>> {
+        let now = SystemTime::now();
+        let mut entries = self.entries()?;
+        let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+        let mut removed = Vec::new();
+        while let Some(oldest) = entries.first() {
+            let expired = older_than.is_some_and(|age| now.duration_since(oldest.modified).unwrap_or_default() > age);
+            if !expired && max_size.is_none_or(|max| size <= max) { break }
+            let entry = entries.remove(0);
+            entry.remove()?;
+            size -= entry.size;
+            removed.push(entry);
+        }
+        Ok(removed)
+    }
+    /// Removes every entry, leaving anything else in the directory alone
+    pub fn clear(&self) -> io::Result<()> { self.gc(None, Some(0)).map(|_| ()) }
+    /// Writes a serialized DFA into the cache under the name its hash gives it, along with its source if given
+    pub(crate) fn store(&self, bytes: &[u8], hash: &blake3::Hash, source: Option<&str>) -> io::Result<PathBuf> {
+        create_dir_all(&self.dir)?;
+        let path = self.dir.join(entry_name(hash));
+        write_bytes(&path, bytes)?;
+        if let Some(source) = source { fs::write(path.with_extension("source"), source)?; }
+        Ok(path)
+    }
+}
+
+impl CacheEntry {
+    /// Checks that the entry still holds the DFA it's named after: that its content hash matches both its contents
+    /// and its name, and that the DFA in it is valid. Entries from before the format was versioned carry no hash,
+    /// so are only checked to load.
+    pub fn verify(&self) -> Result<(), String> {
+        let bytes = fs::read(&self.path).map_err(|e| e.to_string())?;
+        let ser_dfa = SerDfa::decode(&bytes)?;
+        if bytes.starts_with(MAGIC) {
+            let hash = blake3::Hash::from_slice(&bytes[MAGIC.len() + 2..HEADER_LEN]).expect("Header holds a whole hash");
+            let name = entry_name(&hash);
+            if self.path.file_name().is_none_or(|file_name| *file_name != *name) {
+                return Err(format!("holds a different DFA than its name says (its contents belong in {})", name))
+            }
+        }
+        ser_dfa.into_dfa().map(|_| ()).map_err(|defects| {
+            let defects: Vec<String> = defects.iter().map(|defect| defect.to_string()).collect();
+            format!("DFA is invalid: {}", defects.join("; "))
+        })
+    }
+    /// Deletes the entry (and its source) from the cache
+    pub fn remove(&self) -> io::Result<()> {
+        fs::remove_file(&self.path)?;
+        match fs::remove_file(self.path.with_extension("source")) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn entry_name(hash: &blake3::Hash) -> String { format!("sdfa-{}.bc", &hash.to_hex()[..8]) }
+fn is_entry_name(path: &Path) -> bool {
+    path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("sdfa-") && name.ends_with(".bc"))
+}
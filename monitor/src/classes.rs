@@ -0,0 +1,40 @@
+use std::io::{self, BufRead};
+use monitor::{Dfa, LineDfa, LineVerdict, MonitorError, StreamValidator, ValidationFailure};
+use crate::{emit_line, feed_lines, Framing};
+
+/// Given a stream and a line DFA, classifies each line by walking it over the line classes the outer DFA expects next
+/// (the first class in declaration order wins when several accept it), then steps the outer DFA with that class,
+/// writing each line to stdout as it validates. With report_all set, keeps going past failing lines (which are
+/// withheld from stdout and leave the outer DFA where it was) and returns every failure once the stream is exhausted.
+pub fn validate_line_classes(mut stream: Box<dyn BufRead>, line_dfa: &LineDfa, framing: Framing, report_all: bool) -> Result<(), MonitorError> {
+    let mut stdout = io::stdout().lock();
+    let mut state = line_dfa.start_state();
+    let mut failures = Vec::new();
+    let dfas: Vec<&Dfa> = (0..line_dfa.class_len()).map(|class| line_dfa.class_dfa(class)).collect();
+    let mut validator = StreamValidator::with_automata(dfas, framing)?;
+    let mut expected = line_dfa.expected(state);
+    validator.select(&expected)?;
+    feed_lines(stream.as_mut(), &mut validator, |validator, verdict| {
+        match verdict {
+            LineVerdict::Valid(line, accepted_by, cr) => {
+                state = line_dfa.next_state(state, accepted_by[0]);
+                emit_line(&mut stdout, &line, cr, framing)?;
+            },
+            LineVerdict::Invalid(line_no, failure) => {
+                let failure = ValidationFailure::Expected(Box::new(failure), names(line_dfa, &expected));
+                if !report_all { return Err(MonitorError::Validation(vec![(line_no, failure)])) }
+                failures.push((line_no, failure));
+            },
+        }
+        expected = line_dfa.expected(state);
+        validator.select(&expected)
+    })?;
+    //The stream may have ended partway through the sequence of line classes
+    if !line_dfa.is_accepting(state) {
+        let expected = line_dfa.expected(state);
+        failures.push((validator.line_count() + 1, ValidationFailure::Expected(Box::new(ValidationFailure::Whole(Vec::new())), names(line_dfa, &expected))));
+    }
+    if failures.is_empty() { Ok(()) } else { Err(MonitorError::Validation(failures)) }
+}
+
+fn names(line_dfa: &LineDfa, classes: &[usize]) -> Vec<String> { classes.iter().map(|class| line_dfa.class_name(*class).to_string()).collect() }
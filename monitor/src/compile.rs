@@ -0,0 +1,309 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use crate::{byte_dfa::{row_ranges, ByteDfa}, Dfa};
+
+/// Compiles a regular expression in dk.brics.automaton syntax into a minimal DFA matching whole lines of bytes.
+///
+/// Supported syntax (tightest binding last): `a|b` union, `a&b` intersection, concatenation, the postfix repeats
+/// `? * + {n} {n,} {n,m}`, `~a` complement, `[...]`/`[^...]` classes, `.` any byte, `#` the empty language, `@` any
+/// string, `"..."` literal strings, `()` the empty string and `(...)` groups. A backslash makes the character after it
+/// literal (brics has no escape sequences - `\t` is a `t`). As with the Java builder, characters are bytes: anything
+/// above U+00FF matches nothing. Named automata and numeric intervals (`<...>`) aren't supported, so `<` and `>` are
+/// ordinary characters.
+pub(crate) fn compile(regex: &str) -> Result<Dfa, String> {
+    let ast = Parser::new(regex).parse()?;
+    Ok(ByteDfa::from_ast(&ast).minimize().into_dfa())
+}
+
+type ByteSet = [bool; 256];
+
+#[derive(Debug, Clone)]
+enum Ast {
+    Empty, //Matches nothing at all
+    Bytes(Box<ByteSet>), //A single byte out of the set
+    AnyString,
+    Concat(Vec<Ast>), //An empty concatenation is the empty string
+    Union(Box<Ast>, Box<Ast>),
+    Intersection(Box<Ast>, Box<Ast>),
+    Complement(Box<Ast>),
+    Repeat(Box<Ast>, usize, Option<usize>), //Bounds are inclusive, None for no upper bound
+}
+impl Ast {
+    fn char(c: char) -> Self { Self::range(c, c) }
+    /// Characters in the range that are also bytes (the rest are outside the alphabet)
+    fn range(from: char, to: char) -> Self {
+        let mut set = [false; 256];
+        for byte in (from as u32)..=(to as u32).min(255) { set[byte as usize] = true; }
+        Ast::Bytes(Box::new(set))
+    }
+}
+
+//
+//PARSING
+//
+
+/// Recursive descent parser mirroring dk.brics.automaton's RegExp grammar (with every optional feature but named
+/// automata and numeric intervals enabled)
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+impl Parser {
+    fn new(regex: &str) -> Self { Parser { chars: regex.chars().collect(), pos: 0 } }
+    fn parse(mut self) -> Result<Ast, String> {
+        let ast = self.union()?;
+        if self.more() { return Err(format!("end-of-string expected at position {}", self.pos)) }
+        Ok(ast)
+    }
+    fn more(&self) -> bool { self.pos < self.chars.len() }
+    fn peek(&self, any_of: &str) -> bool { self.more() && any_of.contains(self.chars[self.pos]) }
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.more() && self.chars[self.pos] == c;
+        if found { self.pos += 1; }
+        found
+    }
+    fn next(&mut self) -> Result<char, String> {
+        let c = *self.chars.get(self.pos).ok_or("unexpected end-of-string")?;
+        self.pos += 1;
+        Ok(c)
+    }
+    fn union(&mut self) -> Result<Ast, String> {
+        let ast = self.intersection()?;
+        if self.eat('|') { return Ok(Ast::Union(Box::new(ast), Box::new(self.union()?))) }
+        Ok(ast)
+    }
+    fn intersection(&mut self) -> Result<Ast, String> {
+        let ast = self.concat()?;
+        if self.eat('&') { return Ok(Ast::Intersection(Box::new(ast), Box::new(self.intersection()?))) }
+        Ok(ast)
+    }
+    fn concat(&mut self) -> Result<Ast, String> {
+        let mut parts = vec![self.repeat()?];
+        while self.more() && !self.peek(")|&") { parts.push(self.repeat()?); }
+        Ok(if parts.len() == 1 { parts.pop().unwrap() } else { Ast::Concat(parts) })
+    }
+    fn repeat(&mut self) -> Result<Ast, String> {
+        let mut ast = self.complement()?;
+        while self.peek("?*+{") {
+            let (min, max) = match self.next()? {
+                '?' => (0, Some(1)),
+                '*' => (0, None),
+                '+' => (1, None),
+                _ => {
+                    let min = self.number()?.ok_or_else(|| format!("integer expected at position {}", self.pos))?;
+                    let max = if self.eat(',') { self.number()? } else { Some(min) };
+                    if !self.eat('}') { return Err(format!("expected '}}' at position {}", self.pos)) }
+                    (min, max)
+                },
+            };
+            ast = Ast::Repeat(Box::new(ast), min, max);
+        }
+        Ok(ast)
+    }
+    fn number(&mut self) -> Result<Option<usize>, String> {
+        let start = self.pos;
+        while self.peek("0123456789") { self.pos += 1; }
+        if start == self.pos { return Ok(None) }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        digits.parse().map(Some).map_err(|_| format!("repeat count {} is too large", digits))
+    }
+    fn complement(&mut self) -> Result<Ast, String> {
+        if self.eat('~') { return Ok(Ast::Complement(Box::new(self.complement()?))) }
+        self.char_class()
+    }
+    fn char_class(&mut self) -> Result<Ast, String> {
+        if !self.eat('[') { return self.simple() }
+        let negated = self.eat('^');
+        let mut set = Box::new([false; 256]);
+        loop {
+            let Ast::Bytes(class) = self.class_item()? else { unreachable!() };
+            for (byte, in_class) in class.iter().enumerate() { set[byte] |= *in_class; }
+            if self.eat(']') { break }
+        }
+        if negated { for in_set in set.iter_mut() { *in_set = !*in_set; } }
+        Ok(Ast::Bytes(set))
+    }
+    fn class_item(&mut self) -> Result<Ast, String> {
+        let from = self.char_exp()?;
+        if !self.eat('-') { return Ok(Ast::char(from)) }
+        if self.peek("]") {
+            let Ast::Bytes(mut set) = Ast::char(from) else { unreachable!() };
+            set[b'-' as usize] = true;
+            return Ok(Ast::Bytes(set))
+        }
+        let to = self.char_exp()?;
+        if from > to { return Err(format!("invalid range {}-{} at position {}", from, to, self.pos)) }
+        Ok(Ast::range(from, to))
+    }
+    fn simple(&mut self) -> Result<Ast, String> {
+        if self.eat('.') { return Ok(Ast::Bytes(Box::new([true; 256]))) }
+        if self.eat('#') { return Ok(Ast::Empty) }
+        if self.eat('@') { return Ok(Ast::AnyString) }
+        if self.eat('"') {
+            let start = self.pos;
+            while self.more() && !self.peek("\"") { self.pos += 1; }
+            if !self.eat('"') { return Err(format!("expected '\"' at position {}", self.pos)) }
+            return Ok(Ast::Concat(self.chars[start..self.pos - 1].iter().map(|c| Ast::char(*c)).collect()))
+        }
+        if self.eat('(') {
+            if self.eat(')') { return Ok(Ast::Concat(Vec::new())) }
+            let ast = self.union()?;
+            if !self.eat(')') { return Err(format!("expected ')' at position {}", self.pos)) }
+            return Ok(ast)
+        }
+        Ok(Ast::char(self.char_exp()?))
+    }
+    fn char_exp(&mut self) -> Result<char, String> {
+        self.eat('\\');
+        self.next()
+    }
+}
+
+//
+//AUTOMATA CONSTRUCTION
+//
+
+/// Byte NFA with epsilon transitions, built Thompson style
+#[derive(Default)]
+struct Nfa {
+    eps: Vec<Vec<usize>>,
+    trans: Vec<Vec<(u8, u8, usize)>>,
+}
+impl Nfa {
+    fn add_state(&mut self) -> usize {
+        self.eps.push(Vec::new());
+        self.trans.push(Vec::new());
+        self.eps.len() - 1
+    }
+    /// Adds the AST's automaton to the NFA, returning its start and (only) accepting state
+    fn build(&mut self, ast: &Ast) -> (usize, usize) {
+        let (start, end) = (self.add_state(), self.add_state());
+        match ast {
+            Ast::Empty => {},
+            Ast::Bytes(set) => for (lo, hi) in set_ranges(set) { self.trans[start].push((lo, hi, end)); },
+            Ast::AnyString => {
+                self.trans[start].push((0, 255, start));
+                self.eps[start].push(end);
+            },
+            Ast::Concat(parts) => {
+                let mut last = start;
+                for part in parts {
+                    let (s, e) = self.build(part);
+                    self.eps[last].push(s);
+                    last = e;
+                }
+                self.eps[last].push(end);
+            },
+            Ast::Union(a, b) => for part in [a, b] {
+                let (s, e) = self.build(part);
+                self.eps[start].push(s);
+                self.eps[e].push(end);
+            },
+            Ast::Repeat(ast, min, max) => {
+                let mut last = start;
+                for _ in 0..*min {
+                    let (s, e) = self.build(ast);
+                    self.eps[last].push(s);
+                    last = e;
+                }
+                match max {
+                    None => { //Kleene star on top of the required copies
+                        let (s, e) = self.build(ast);
+                        self.eps[last].push(s);
+                        self.eps[e].push(last);
+                    },
+                    Some(max) => for _ in *min..*max { //Optional copies, each able to skip to the end
+                        let (s, e) = self.build(ast);
+                        self.eps[last].push(s);
+                        self.eps[last].push(end);
+                        last = e;
+                    },
+                }
+                if max.is_none_or(|max| *min <= max) { self.eps[last].push(end); }
+            },
+            //Complement and intersection need deterministic operands, so they're built as DFAs and embedded
+            Ast::Intersection(..) | Ast::Complement(_) => {
+                let dfa = ByteDfa::from_ast(ast).minimize();
+                let states: Vec<usize> = (0..dfa.len()).map(|_| self.add_state()).collect();
+                self.eps[start].push(states[dfa.start]);
+                for (state, row) in dfa.next.iter().enumerate().skip(1) {
+                    for (lo, hi, next) in row_ranges(row) { self.trans[states[state]].push((lo, hi, states[next])); }
+                    if dfa.accept[state] { self.eps[states[state]].push(end); }
+                }
+            },
+        }
+        (start, end)
+    }
+    fn closure(&self, states: &mut Vec<usize>) {
+        let mut seen: HashSet<usize> = states.iter().copied().collect();
+        let mut stack = states.clone();
+        while let Some(state) = stack.pop() {
+            for next in &self.eps[state] {
+                if seen.insert(*next) { stack.push(*next); states.push(*next); }
+            }
+        }
+        states.sort_unstable();
+    }
+}
+
+impl ByteDfa {
+    fn from_ast(ast: &Ast) -> Self {
+        match ast {
+            Ast::Complement(ast) => ByteDfa::from_ast(ast).complement(),
+            Ast::Intersection(a, b) => ByteDfa::from_ast(a).intersect(&ByteDfa::from_ast(b)),
+            _ => {
+                let mut nfa = Nfa::default();
+                let (start, end) = nfa.build(ast);
+                ByteDfa::determinize(&nfa, start, end)
+            },
+        }
+    }
+    /// Subset construction, walking one representative byte per class of bytes the NFA never distinguishes between
+    fn determinize(nfa: &Nfa, start: usize, end: usize) -> Self {
+        let mut bounds = [false; 257];
+        (bounds[0], bounds[256]) = (true, true);
+        for (lo, hi, _) in nfa.trans.iter().flatten() { bounds[*lo as usize] = true; bounds[*hi as usize + 1] = true; }
+        let classes: Vec<(usize, usize)> = {
+            let starts: Vec<usize> = (0..=256).filter(|b| bounds[*b]).collect();
+            starts.windows(2).map(|w| (w[0], w[1] - 1)).collect()
+        };
+        let mut dfa = ByteDfa { start: 1, accept: vec![false], next: vec![[0; 256]] };
+        let mut ids: HashMap<Vec<usize>, usize> = HashMap::from([(Vec::new(), 0)]);
+        let mut initial = vec![start];
+        nfa.closure(&mut initial);
+        let mut queue = VecDeque::from([initial.clone()]);
+        dfa.accept.push(initial.contains(&end));
+        dfa.next.push([0; 256]);
+        ids.insert(initial, 1);
+        while let Some(set) = queue.pop_front() {
+            let id = ids[&set];
+            for (lo, hi) in &classes {
+                let byte = *lo as u8;
+                let mut targets: Vec<usize> = set.iter()
+                    .flat_map(|state| nfa.trans[*state].iter().filter(|(l, h, _)| *l <= byte && byte <= *h).map(|(_, _, next)| *next))
+                    .collect();
+                targets.sort_unstable();
+                targets.dedup();
+                nfa.closure(&mut targets);
+                let target = match ids.get(&targets) {
+                    Some(target) => *target,
+                    None => {
+                        let target = dfa.len();
+                        dfa.accept.push(targets.contains(&end));
+                        dfa.next.push([0; 256]);
+                        ids.insert(targets.clone(), target);
+                        queue.push_back(targets);
+                        target
+                    },
+                };
+                for slot in &mut dfa.next[id][*lo..=*hi] { *slot = target; }
+            }
+        }
+        dfa
+    }
+}
+
+/// Maximal byte ranges in the set
+fn set_ranges(set: &ByteSet) -> Vec<(u8, u8)> {
+    let row: Vec<usize> = set.iter().map(|in_set| *in_set as usize).collect();
+    row_ranges(&row).into_iter().map(|(lo, hi, _)| (lo, hi)).collect()
+}
@@ -0,0 +1,247 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use memmap2::Mmap;
+use regex_automata::util::primitives::StateID;
+use crate::{byte_dfa::row_ranges, TransitionDesc, TransitionTable};
+
+/// Flat serialized DFAs start with these bytes, then the layout version (a little endian u16) and two bytes of
+/// padding. Little endian u32s for the stride's log2, the number of rows and the (premultiplied) start state follow,
+/// then the 256 byte classes, the table itself as little endian u32s and one 0 or 1 match flag per row - so the
+/// table starts 4 byte aligned and can be used straight out of a memory mapping.
+pub(crate) const FLAT_MAGIC: &[u8; 4] = b"SDFM";
+const FLAT_VERSION: u16 = 1;
+const FLAT_TABLE_OFFSET: usize = 8 + 3 * 4 + 256;
+
+/// Memory a table's transitions and match flags live in - only held on to, as the table points into it
+#[allow(dead_code)]
+enum Backing {
+    Heap(Vec<u32>, Vec<u8>),
+    Mapped(Mmap),
+}
+
+/// Flat, premultiplied transition table compiled from a sparse `TransitionTable`. Bytes are first mapped to
+/// equivalence classes (bytes that no transition in the DFA ever distinguishes between share a class), and each
+/// state owns a row of `1 << stride2` slots. State IDs handed out by this table are premultiplied by the stride
+/// (ie. they are the index of their row's first slot), so a transition is a single array load:
+/// `table[state + classes[byte]]`.
+pub(crate) struct DenseTable {
+    classes: [u8; 256],
+    stride2: usize,
+    table: *const [u32], //Into backing
+    matches: *const [u8], //Into backing, one 0 or 1 per row
+    start: StateID,
+    ids: Option<HashMap<StateID, StateID>>, //Sparse (producer-assigned) ID -> premultiplied ID, if they aren't row numbers
+    _backing: Backing,
+}
+//SAFETY: the table and match flags are never written to after construction, and the heap allocation or mapping they
+//point into lives (without moving) as long as the table does
+unsafe impl Send for DenseTable {}
+unsafe impl Sync for DenseTable {}
+impl DenseTable {
+    /// Compiles the sparse table. The dead state is always placed in row 0 so that its premultiplied ID is 0;
+    /// the remaining states follow in ascending order of their sparse ID so compilation is deterministic.
+    pub(crate) fn compile(
+        start_state: StateID,
+        match_states: &HashSet<StateID>,
+        transition_table: &TransitionTable,
+        dead_state: StateID
+    ) -> Self {
+        let classes = byte_classes(transition_table);
+        let class_len = classes[255] as usize + 1;
+        let stride2 = class_len.next_power_of_two().trailing_zeros() as usize;
+        //Gather every state the DFA can mention - targets/start/match states without a table entry have no
+        //transitions out of them (all bytes lead to the dead state)
+        let mut sparse_ids: BTreeSet<StateID> = transition_table.keys().copied().collect();
+        sparse_ids.insert(start_state);
+        sparse_ids.extend(match_states.iter().copied());
+        for trans_descs in transition_table.values() {
+            sparse_ids.extend(trans_descs.iter().map(TransitionDesc::next_state));
+        }
+        sparse_ids.remove(&dead_state);
+        let order: Vec<StateID> = std::iter::once(dead_state).chain(sparse_ids).collect();
+        let ids: HashMap<StateID, StateID> = order
+            .iter().enumerate()
+            .map(|(i, sid)| (*sid, StateID::must(i << stride2)))
+            .collect();
+        //Fill each state's row by probing the sparse table with one representative byte per class
+        let mut representatives = vec![0u8; class_len];
+        for byte in (0..=255u8).rev() { representatives[classes[byte as usize] as usize] = byte; }
+        let dead = ids[&dead_state].as_u32();
+        let mut table = vec![dead; order.len() << stride2];
+        for (row, sid) in order.iter().enumerate() {
+            let Some(trans_descs) = transition_table.get(sid) else { continue };
+            for (class, byte) in representatives.iter().enumerate() {
+                if let Some(next) = sparse_next_state(trans_descs, *byte) {
+                    table[(row << stride2) + class] = ids[&next].as_u32();
+                }
+            }
+        }
+        let matches = order.iter().map(|sid| u8::from(match_states.contains(sid))).collect();
+        DenseTable::on_heap(classes, stride2, table, matches, ids[&start_state], Some(ids))
+    }
+    fn on_heap(classes: [u8; 256], stride2: usize, table: Vec<u32>, matches: Vec<u8>, start: StateID, ids: Option<HashMap<StateID, StateID>>) -> Self {
+        let (table_ptr, matches_ptr) = (table.as_slice() as *const [u32], matches.as_slice() as *const [u8]);
+        DenseTable { classes, stride2, table: table_ptr, matches: matches_ptr, start, ids, _backing: Backing::Heap(table, matches) }
+    }
+    fn table(&self) -> &[u32] {
+        //SAFETY: points into the backing, which lives as long as self
+        unsafe { &*self.table }
+    }
+    fn matches(&self) -> &[u8] {
+        //SAFETY: as for table
+        unsafe { &*self.matches }
+    }
+    #[inline(always)]
+    pub(crate) fn next_state(&self, current: StateID, input: u8) -> StateID {
+        StateID::new_unchecked(self.table()[current.as_usize() + self.classes[input as usize] as usize] as usize)
+    }
+    /// # Safety
+    /// `current` must be a state ID produced by this table
+    #[inline(always)]
+    pub(crate) unsafe fn next_state_unchecked(&self, current: StateID, input: u8) -> StateID {
+        //SAFETY: classes has 256 entries and every class < 1 << stride2, and every ID produced by this table is
+        //the start of a full row
+        unsafe {
+            let class = *self.classes.get_unchecked(input as usize) as usize;
+            StateID::new_unchecked(*self.table().get_unchecked(current.as_usize() + class) as usize)
+        }
+    }
+    pub(crate) fn start(&self) -> StateID { self.start }
+    pub(crate) fn is_match(&self, id: StateID) -> bool { self.matches()[self.row(id)] == 1 }
+    /// Translates a sparse (producer-assigned) state ID into the premultiplied ID used by this table
+    pub(crate) fn id(&self, sparse: StateID) -> Option<StateID> {
+        match &self.ids {
+            Some(ids) => ids.get(&sparse).copied(),
+            None => (sparse.as_usize() < self.state_len()).then(|| self.id_of_row(sparse.as_usize())),
+        }
+    }
+    pub(crate) fn state_len(&self) -> usize { self.matches().len() }
+    /// Row of the table a (premultiplied) state ID points at - rows are numbered densely from the dead state's 0
+    pub(crate) fn row(&self, id: StateID) -> usize { id.as_usize() >> self.stride2 }
+    pub(crate) fn id_of_row(&self, row: usize) -> StateID { StateID::must(row << self.stride2) }
+    pub(crate) fn alphabet_len(&self) -> usize { self.classes[255] as usize + 1 }
+    /// Sparse description of the table, numbering states by their row (so the dead state is 0) - returns the start
+    /// state, match states and transition table
+    pub(crate) fn to_sparse(&self) -> (StateID, HashSet<StateID>, TransitionTable) {
+        let dead_state = StateID::ZERO;
+        let mut transition_table = TransitionTable::from([(dead_state, vec![TransitionDesc::Range(u8::MIN, u8::MAX, dead_state)])]);
+        for row in 1..self.state_len() {
+            let targets: Vec<usize> = (0..=255u8).map(|byte| self.row(self.next_state(self.id_of_row(row), byte))).collect();
+            let trans_descs = row_ranges(&targets).into_iter()
+                .map(|(lo, hi, next)| if lo == hi { TransitionDesc::Match(lo, StateID::must(next)) } else { TransitionDesc::Range(lo, hi, StateID::must(next)) })
+                .collect();
+            transition_table.insert(StateID::must(row), trans_descs);
+        }
+        let match_states = (0..self.state_len()).filter(|row| self.matches()[*row] == 1).map(StateID::must).collect();
+        (StateID::must(self.row(self.start)), match_states, transition_table)
+    }
+    /// The table in the flat serialized layout (see `FLAT_MAGIC`)
+    pub(crate) fn to_flat(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FLAT_TABLE_OFFSET + self.table().len() * 4 + self.state_len());
+        bytes.extend_from_slice(FLAT_MAGIC);
+        bytes.extend_from_slice(&FLAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        for n in [self.stride2, self.state_len(), self.start.as_usize()] { bytes.extend_from_slice(&(n as u32).to_le_bytes()); }
+        bytes.extend_from_slice(&self.classes);
+        for next in self.table() { bytes.extend_from_slice(&next.to_le_bytes()); }
+        bytes.extend_from_slice(self.matches());
+        bytes
+    }
+    /// Uses a mapped file in the flat layout as the table, after checking (without allocating) that every ID in it
+    /// points at a row. The table is only copied if it can't be read in place (on big endian targets).
+    ///
+    /// # Safety
+    /// Lookups trust the table once it has been checked, so nothing may change or truncate the file while the table
+    /// is alive.
+    pub(crate) unsafe fn from_flat(map: Mmap) -> Result<Self, String> {
+        let layout = FlatLayout::read(&map)?;
+        //SAFETY: any 4 bytes are a valid u32
+        let (prefix, table, suffix) = unsafe { layout.table_bytes.align_to::<u32>() };
+        if cfg!(target_endian = "little") && prefix.is_empty() && suffix.is_empty() {
+            layout.check(table)?;
+            let (table, matches) = (table as *const [u32], layout.matches as *const [u8]);
+            let FlatLayout { classes, stride2, start, .. } = layout;
+            return Ok(DenseTable { classes, stride2, table, matches, start, ids: None, _backing: Backing::Mapped(map) })
+        }
+        Self::copy_flat(layout)
+    }
+    /// Copies a table in the flat layout onto the heap, checking the copy
+    pub(crate) fn from_flat_bytes(bytes: &[u8]) -> Result<Self, String> { Self::copy_flat(FlatLayout::read(bytes)?) }
+    fn copy_flat(layout: FlatLayout) -> Result<Self, String> {
+        let table: Vec<u32> = layout.table_bytes.chunks_exact(4).map(|next| u32::from_le_bytes(next.try_into().unwrap())).collect();
+        layout.check(&table)?;
+        Ok(DenseTable::on_heap(layout.classes, layout.stride2, table, layout.matches.to_vec(), layout.start, None))
+    }
+}
+
+/// The parts of a table in the flat layout, with its header checked - but not yet its transitions
+struct FlatLayout<'a> {
+    classes: [u8; 256],
+    stride2: usize,
+    rows: usize,
+    start: StateID,
+    table_bytes: &'a [u8],
+    matches: &'a [u8],
+}
+impl<'a> FlatLayout<'a> {
+    fn read(bytes: &'a [u8]) -> Result<Self, String> {
+        let truncated = || "serialized DFA is truncated".to_string();
+        let field = |at: usize| bytes.get(at..at + 4).map(|field| u32::from_le_bytes(field.try_into().unwrap()) as usize).ok_or_else(truncated);
+        let version = bytes.get(4..6).map(|version| u16::from_le_bytes(version.try_into().unwrap())).ok_or_else(truncated)?;
+        if version != FLAT_VERSION { return Err(format!(
+            "flat serialized DFA layout version {} is not supported (this monitor reads version {}) - re-serialize it with this monitor", version, FLAT_VERSION
+        )) }
+        let (stride2, rows, start) = (field(8)?, field(12)?, field(16)?);
+        let classes: [u8; 256] = bytes.get(20..FLAT_TABLE_OFFSET).ok_or_else(truncated)?.try_into().unwrap();
+        if stride2 > 8 || classes[255] as usize >= 1 << stride2 { return Err(corrupt("byte classes don't fit the stride")) }
+        if classes[0] != 0 || classes.windows(2).any(|pair| pair[1] != pair[0] && pair[1] != pair[0] + 1) { return Err(corrupt("byte classes aren't numbered in order")) }
+        if rows == 0 || rows > StateID::LIMIT >> stride2 { return Err(corrupt("bad row count")) }
+        let table_len = rows << stride2;
+        if bytes.len() != FLAT_TABLE_OFFSET + table_len * 4 + rows { return Err(corrupt("length doesn't match its row count")) }
+        let layout = FlatLayout {
+            classes, stride2, rows, start: StateID::must(start),
+            table_bytes: &bytes[FLAT_TABLE_OFFSET..FLAT_TABLE_OFFSET + table_len * 4],
+            matches: &bytes[FLAT_TABLE_OFFSET + table_len * 4..],
+        };
+        if start == 0 || !layout.is_row(start) { return Err(corrupt("bad start state")) }
+        if layout.matches.iter().any(|flag| *flag > 1) || layout.matches[0] == 1 { return Err(corrupt("bad match flags")) }
+        Ok(layout)
+    }
+    fn is_row(&self, id: usize) -> bool { id & ((1 << self.stride2) - 1) == 0 && id >> self.stride2 < self.rows }
+    /// Checks that every transition leads to a row, and none out of the dead state
+    fn check(&self, table: &[u32]) -> Result<(), String> {
+        if table.iter().any(|next| !self.is_row(*next as usize)) { return Err(corrupt("transition to a state that doesn't exist")) }
+        if table[..1 << self.stride2].iter().any(|next| *next != 0) { return Err(corrupt("transitions leading out of the dead state")) }
+        Ok(())
+    }
+}
+
+fn corrupt(what: &str) -> String { format!("serialized DFA is corrupt ({})", what) }
+
+/// Walks a single state's transition descriptions in order, returning the target of the first one that accepts
+/// `input` (if any)
+pub(crate) fn sparse_next_state(trans_descs: &[TransitionDesc], input: u8) -> Option<StateID> {
+    trans_descs.iter().find_map(|trans_desc| match *trans_desc {
+        TransitionDesc::Match(byte, next_state) if input == byte => Some(next_state),
+        TransitionDesc::Range(start_byte, end_byte, next_state) if input >= start_byte && input <= end_byte => Some(next_state),
+        _ => None,
+    })
+}
+
+/// Partitions the byte alphabet into equivalence classes: a new class starts at every byte where some transition's
+/// range starts or just after one ends. Returns the class of every byte; classes are numbered in ascending order so
+/// the class of byte 255 is the highest.
+fn byte_classes(transition_table: &TransitionTable) -> [u8; 256] {
+    let mut boundaries = [false; 256];
+    for trans_desc in transition_table.values().flatten() {
+        let (start_byte, end_byte) = trans_desc.range();
+        boundaries[start_byte as usize] = true;
+        if end_byte < u8::MAX { boundaries[end_byte as usize + 1] = true; }
+    }
+    let mut classes = [0u8; 256];
+    let mut class = 0u8;
+    for byte in 1..256 {
+        if boundaries[byte] { class += 1; }
+        classes[byte] = class;
+    }
+    classes
+}
@@ -0,0 +1,197 @@
+use std::{fmt, io, path::{Path, PathBuf}};
+use regex_automata::util::primitives::StateID;
+
+/// Why a single line of a stream failed validation. Lines are kept as the raw bytes read from the stream - they
+/// need not be valid UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationFailure {
+    Partial(Vec<u8>, Option<usize>), //Some prefix of the line matched, but the line as a whole did not - holds the line, or if the DFA died partway through it, the line up to and including the byte that killed it and that byte's offset
+    Whole(Vec<u8>), //No prefix of the line matched
+    Dead(Vec<u8>, usize), //The DFA died partway through the line - holds the line up to and including the byte that killed it and that byte's offset
+    MissingCr(Vec<u8>), //CRLF line endings were required, but the line wasn't terminated by one
+    Expected(Box<ValidationFailure>, Vec<String>), //The line failed to match any of the named types expected at its place in the stream
+    Field(Vec<u8>, usize, Vec<u8>), //A column of the line failed its type - holds the line, the column's 1-based index and its value
+    ColumnCount(Vec<u8>, usize), //The line was split into a number of columns its type doesn't allow - holds the line and that number
+}
+impl ValidationFailure {
+    pub fn line(&self) -> &[u8] {
+        match self { 
+            ValidationFailure::Partial(line, _) | ValidationFailure::Whole(line) | ValidationFailure::Dead(line, _) | ValidationFailure::MissingCr(line)
+                | ValidationFailure::Field(line, _, _) | ValidationFailure::ColumnCount(line, _) => line,
+            ValidationFailure::Expected(failure, _) => failure.line(),
+        }
+    }
+}
+
+/// Lists expected type names for error messages
+fn expected(names: &[String]) -> String {
+    if names.is_empty() { String::from("end of stream") } else { names.join(" or ") }
+}
+
+/// Writes the message for a stream that failed validation on a single line
+fn write_failure(f: &mut fmt::Formatter<'_>, line_no: usize, failure: &ValidationFailure, rendering: LineRendering) -> fmt::Result {
+    match failure {
+        ValidationFailure::Partial(l, None) => write!(f, "Validation failed (partial match) on line {}\nIncriminating line: {}", line_no, rendering.render(l)),
+        ValidationFailure::Partial(l, Some(offset)) => write!(f, "Validation failed (partial match) at byte {} of line {}\nIncriminating line (up to failure): {}", offset, line_no, rendering.render(l)),
+        ValidationFailure::Whole(l) => write!(f, "Validation failed on line {}\nIncriminating line: {}", line_no, rendering.render(l)),
+        ValidationFailure::Dead(l, offset) => write!(f, "Validation failed at byte {} of line {}\nIncriminating line (up to failure): {}", offset, line_no, rendering.render(l)),
+        ValidationFailure::MissingCr(l) => write!(f, "Validation failed (missing \\r before delimiter) on line {}\nIncriminating line: {}", line_no, rendering.render(l)),
+        ValidationFailure::Field(l, column, value) => write!(f, "Validation failed on column {} of line {}\nIncriminating value: {}\nIncriminating line: {}", column, line_no, rendering.render(value), rendering.render(l)),
+        ValidationFailure::ColumnCount(l, columns) => write!(f, "Validation failed (unexpected column count {}) on line {}\nIncriminating line: {}", columns, line_no, rendering.render(l)),
+        ValidationFailure::Expected(failure, names) => {
+            write_failure(f, line_no, failure, rendering)?;
+            write!(f, "\nExpected: {}", expected(names))
+        }
+    }
+}
+
+/// Writes the entry for a single failing line in a list of failures
+fn write_listed_failure(f: &mut fmt::Formatter<'_>, line_no: usize, failure: &ValidationFailure, rendering: LineRendering) -> fmt::Result {
+    match failure {
+        ValidationFailure::Partial(l, None) => write!(f, "\nLine {} (partial match): {}", line_no, rendering.render(l)),
+        ValidationFailure::Partial(l, Some(offset)) => write!(f, "\nLine {} (partial match, failed at byte {}): {}", line_no, offset, rendering.render(l)),
+        ValidationFailure::Whole(l) => write!(f, "\nLine {}: {}", line_no, rendering.render(l)),
+        ValidationFailure::Dead(l, offset) => write!(f, "\nLine {} (failed at byte {}): {}", line_no, offset, rendering.render(l)),
+        ValidationFailure::MissingCr(l) => write!(f, "\nLine {} (missing \\r before delimiter): {}", line_no, rendering.render(l)),
+        ValidationFailure::Field(l, column, value) => write!(f, "\nLine {} (column {}: {}): {}", line_no, column, rendering.render(value), rendering.render(l)),
+        ValidationFailure::ColumnCount(l, columns) => write!(f, "\nLine {} (unexpected column count {}): {}", line_no, columns, rendering.render(l)),
+        ValidationFailure::Expected(failure, names) => {
+            write_listed_failure(f, line_no, failure, rendering)?;
+            write!(f, " (expected {})", expected(names))
+        }
+    }
+}
+
+/// How the (possibly non-UTF-8) lines in validation failures get rendered into error messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineRendering {
+    #[default]
+    Escaped, //Invalid UTF-8 bytes are written as \xNN escapes, so the exact offending bytes are visible
+    Lossy, //Invalid UTF-8 sequences are replaced with U+FFFD
+}
+impl LineRendering {
+    pub fn render(&self, line: &[u8]) -> String {
+        match self {
+            LineRendering::Lossy => String::from_utf8_lossy(line).into_owned(),
+            LineRendering::Escaped => {
+                let mut rendered = String::new();
+                for chunk in line.utf8_chunks() {
+                    rendered += chunk.valid();
+                    for byte in chunk.invalid() { rendered += format!("\\x{:02x}", byte).as_str(); }
+                }
+                rendered
+            }
+        }
+    }
+}
+
+/// A structural problem with a DFA description, as found by `Dfa::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DfaDefect {
+    UnknownStartState(StateID), //The start state has no transition table entry
+    UnknownMatchState(StateID), //A match state has no transition table entry
+    DanglingTarget(StateID, StateID), //A state transitions to a state with no transition table entry
+    OverlappingRanges(StateID, (u8, u8), (u8, u8)), //Two of a state's transitions accept some of the same bytes
+    UnsortedRanges(StateID), //A state's transitions aren't in ascending order of their first byte
+    UnreachableState(StateID), //No input leads from the start state to the state
+    DeadStateMisuse(StateID, &'static str), //The reserved dead state ID is used as something else - holds what it's used as
+    StateIdTooLarge(usize), //A state ID is too large to be used as one
+}
+impl fmt::Display for DfaDefect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DfaDefect::UnknownStartState(sid) => write!(f, "start state {} has no transition table entry", sid.as_usize()),
+            DfaDefect::UnknownMatchState(sid) => write!(f, "match state {} has no transition table entry", sid.as_usize()),
+            DfaDefect::DanglingTarget(sid, target) => write!(f, "state {} transitions to state {}, which has no transition table entry", sid.as_usize(), target.as_usize()),
+            DfaDefect::OverlappingRanges(sid, (s1, e1), (s2, e2)) => write!(f, "state {} has overlapping transitions on bytes {}-{} and {}-{}", sid.as_usize(), s1, e1, s2, e2),
+            DfaDefect::UnsortedRanges(sid) => write!(f, "state {} has transitions out of order", sid.as_usize()),
+            DfaDefect::UnreachableState(sid) => write!(f, "state {} is unreachable from the start state", sid.as_usize()),
+            DfaDefect::DeadStateMisuse(sid, misuse) => write!(f, "reserved dead state {} {}", sid.as_usize(), misuse),
+            DfaDefect::StateIdTooLarge(sid) => write!(f, "state ID {} is too large (the largest allowed is {})", sid, StateID::MAX.as_usize()),
+        }
+    }
+}
+
+/// Everything that can go wrong while monitoring a stream. Only `Validation` means the stream did not match its
+/// type - every other variant means the monitor itself could not do its job.
+#[derive(Debug)]
+pub enum MonitorError {
+    Validation(Vec<(usize, ValidationFailure)>), //Every failing line alongside its 1-based line number
+    DfaLoad { path: PathBuf, reason: String },
+    InvalidDfa { path: PathBuf, defects: Vec<DfaDefect> }, //The DFA loaded, but failed validation
+    RegexCompile { regex: String, reason: String },
+    Io(io::Error),
+    TrapSetup(String),
+    Usage(String), //Invalid combination of arguments
+}
+impl MonitorError {
+    pub const VALIDATION_EXIT_CODE: i32 = 1;
+    pub const USAGE_EXIT_CODE: i32 = 2; //Matches clap's exit code for argument parsing errors
+    pub const DFA_LOAD_EXIT_CODE: i32 = 3;
+    pub const REGEX_COMPILE_EXIT_CODE: i32 = 4;
+    pub const IO_EXIT_CODE: i32 = 5;
+    pub const TRAP_SETUP_EXIT_CODE: i32 = 6;
+
+    /// Process exit code the monitor binary reports this error with
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MonitorError::Validation(_) => Self::VALIDATION_EXIT_CODE,
+            MonitorError::Usage(_) => Self::USAGE_EXIT_CODE,
+            MonitorError::DfaLoad { .. } | MonitorError::InvalidDfa { .. } => Self::DFA_LOAD_EXIT_CODE,
+            MonitorError::RegexCompile { .. } => Self::REGEX_COMPILE_EXIT_CODE,
+            MonitorError::Io(_) => Self::IO_EXIT_CODE,
+            MonitorError::TrapSetup(_) => Self::TRAP_SETUP_EXIT_CODE,
+        }
+    }
+    pub(crate) fn dfa_load(path: &Path, reason: impl fmt::Display) -> Self {
+        MonitorError::DfaLoad { path: path.to_path_buf(), reason: reason.to_string() }
+    }
+    /// Displays the error, rendering any offending lines as specified (the Display implementation escapes them)
+    pub fn display(&self, rendering: LineRendering) -> impl fmt::Display + '_ { DisplayMonitorError(self, rendering) }
+}
+impl fmt::Display for MonitorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { DisplayMonitorError(self, LineRendering::default()).fmt(f) }
+}
+
+struct DisplayMonitorError<'a>(&'a MonitorError, LineRendering);
+impl fmt::Display for DisplayMonitorError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let DisplayMonitorError(err, rendering) = self;
+        match err {
+            MonitorError::Validation(failures) => match failures.as_slice() {
+                [(line_no, failure)] => write_failure(f, *line_no, failure, *rendering),
+                _ => {
+                    write!(f, "Validation failed on {} line(s)", failures.len())?;
+                    for (line_no, failure) in failures { write_listed_failure(f, *line_no, failure, *rendering)?; }
+                    Ok(())
+                }
+            },
+            MonitorError::DfaLoad { path, reason } => write!(f, "Failed to load DFA from {:?}: {}", path, reason),
+            MonitorError::InvalidDfa { path, defects } => {
+                write!(f, "DFA loaded from {:?} is invalid:", path)?;
+                for defect in defects { write!(f, "\n  - {}", defect)?; }
+                Ok(())
+            },
+            MonitorError::RegexCompile { regex, reason } => write!(f, "Failed to compile regular expression {:?}: {}", regex, reason),
+            MonitorError::Io(e) => write!(f, "I/O error: {}", e),
+            MonitorError::TrapSetup(reason) => write!(f, "Trap not properly set up: {}", reason),
+            MonitorError::Usage(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+impl std::error::Error for MonitorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MonitorError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+/// I/O errors wrapping a `MonitorError` (as `ValidatingReader` and `ValidatingWriter` fail with) are unwrapped back
+/// into it
+impl From<io::Error> for MonitorError {
+    fn from(e: io::Error) -> Self {
+        if !e.get_ref().is_some_and(|inner| inner.is::<MonitorError>()) { return MonitorError::Io(e) }
+        *e.into_inner().expect("Checked for an inner error").downcast().expect("Checked the inner error's type")
+    }
+}
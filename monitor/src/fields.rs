@@ -0,0 +1,99 @@
+use std::io::{self, BufRead};
+use regex_automata::{dfa::Automaton, util::start::Config, Anchored};
+use monitor::{CrlfMode, MonitorError, ValidationFailure};
+use crate::{emit_line, sections::LineCount, Framing};
+
+/// Types for the columns of a line, which is split on a separator byte or on runs of whitespace
+pub struct Fields {
+    pub columns: Vec<Box<dyn Automaton + Send + Sync>>, //Columns past the last one given share its type
+    pub rest: Option<Box<dyn Automaton + Send + Sync>>, //Type for whatever follows the given columns, left unsplit
+    pub separator: Option<u8>, //None to split on runs of spaces and tabs, ignoring leading and trailing ones
+    pub count: LineCount, //Number of columns a line may have (not counting the rest of line)
+}
+impl Fields {
+    /// Splits a line into its columns and, with a rest of line type, the unsplit remainder after them (empty if the
+    /// line runs out of columns first)
+    fn split<'l>(&self, line: &'l [u8]) -> (Vec<&'l [u8]>, Option<&'l [u8]>) {
+        let limit = self.rest.as_ref().map(|_| self.columns.len());
+        let mut columns = Vec::new();
+        match self.separator {
+            Some(sep) => {
+                let mut parts = line.splitn(limit.map_or(usize::MAX, |n| n + 1), |byte| *byte == sep);
+                columns.extend(parts.by_ref().take(limit.unwrap_or(usize::MAX)));
+                (columns, limit.map(|_| parts.next().unwrap_or_default()))
+            },
+            None => {
+                let is_space = |byte: &u8| *byte == b' ' || *byte == b'\t';
+                let mut i = 0;
+                loop {
+                    while i < line.len() && is_space(&line[i]) { i += 1; }
+                    if i == line.len() || limit == Some(columns.len()) { break }
+                    let start = i;
+                    while i < line.len() && !is_space(&line[i]) { i += 1; }
+                    columns.push(&line[start..i]);
+                }
+                (columns, limit.map(|_| &line[i..]))
+            },
+        }
+    }
+    /// Checks a line's column count, then each of its columns (and the rest of it) in order
+    fn validate(&self, line: &[u8]) -> Result<Result<(), ValidationFailure>, MonitorError> {
+        let (columns, rest) = self.split(line);
+        if !self.count.contains(columns.len()) { return Ok(Err(ValidationFailure::ColumnCount(line.to_vec(), columns.len()))) }
+        for (i, value) in columns.iter().enumerate() {
+            let dfa = &self.columns[i.min(self.columns.len() - 1)];
+            if !column_matches(dfa.as_ref(), value)? { return Ok(Err(ValidationFailure::Field(line.to_vec(), i + 1, value.to_vec()))) }
+        }
+        if let (Some(dfa), Some(value)) = (&self.rest, rest) && !column_matches(dfa.as_ref(), value)? {
+            return Ok(Err(ValidationFailure::Field(line.to_vec(), columns.len() + 1, value.to_vec())))
+        }
+        Ok(Ok(()))
+    }
+}
+
+/// Whether a column's type accepts its value, walking the value over its automaton (quit states count as dead)
+fn column_matches(dfa: &dyn Automaton, value: &[u8]) -> Result<bool, MonitorError> {
+    let mut state = dfa.start_state(&Config::new().anchored(Anchored::Yes))
+        .map_err(|e| MonitorError::Usage(format!("Couldn't bring DFA to its start state: {}", e)))?;
+    for byte in value {
+        state = dfa.next_state(state, *byte);
+        if dfa.is_dead_state(state) || dfa.is_quit_state(state) { return Ok(false) }
+    }
+    Ok(dfa.is_match_state(dfa.next_eoi_state(state)))
+}
+
+/// Takes the delimiter and any \r the framing handles off a line read through its delimiter, returning whether a \r
+/// was taken off - or the failure, if the framing requires one and the line has none
+fn unframe(line: &mut Vec<u8>, framing: Framing) -> Result<bool, ValidationFailure> {
+    if line.last() == Some(&framing.delimiter) { line.pop(); }
+    let cr = framing.crlf.is_some() && line.last() == Some(&b'\r');
+    if cr { line.pop(); }
+    if framing.crlf == Some(CrlfMode::Require) && !cr { return Err(ValidationFailure::MissingCr(line.clone())) }
+    Ok(cr)
+}
+
+/// Given a stream and the types of its columns, validates each line column by column, writing each line to stdout as
+/// it validates. With report_all set, keeps going past failing lines (which are withheld from stdout) and returns
+/// every failure once the stream is exhausted.
+pub fn validate_fields(mut stream: Box<dyn BufRead>, fields: Fields, framing: Framing, report_all: bool) -> Result<(), MonitorError> {
+    let mut stdout = io::stdout().lock();
+    let mut failures = Vec::new();
+    let (mut line, mut line_no) = (Vec::new(), 0);
+    loop {
+        line.clear();
+        if stream.read_until(framing.delimiter, &mut line)? == 0 { break }
+        line_no += 1;
+        let failure = match unframe(&mut line, framing) {
+            Ok(cr) => match fields.validate(&line)? {
+                Ok(()) => { emit_line(&mut stdout, &line, cr, framing)?; continue },
+                Err(failure) => failure,
+            },
+            Err(failure) => failure,
+        };
+        if !report_all { return Err(MonitorError::Validation(vec![(line_no, failure)])) }
+        failures.push((line_no, failure));
+    }
+    //As in the other modes, an empty stream is a single empty line
+    if line_no == 0 && let Err(failure) = fields.validate(&[])? { failures.push((1, failure)); }
+    if failures.is_empty() { Ok(()) } else { Err(MonitorError::Validation(failures)) }
+}
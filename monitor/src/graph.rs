@@ -0,0 +1,111 @@
+use std::fmt::Write;
+use regex_automata::util::primitives::StateID;
+use crate::{Dfa, Sparse};
+
+/// Bytes escaped with a backslash in edge labels, which are written in the regex crate's syntax (the monitor's `-r`):
+/// its metacharacters and `Dfa::from_regex`'s, so that labels without `\t`, `\n`, `\r` or `\xNN` escapes read the same
+/// in both
+const META: &[u8] = br#"\|&?*+{}~[].#@"()^-"#;
+
+/// Each state (in ascending order of ID) with its outgoing edges, one per target state ordered by lowest byte and
+/// labelled with the class of bytes leading there. Edges into the dead state are left out if `hide_dead` is set.
+fn edges(dfa: &Sparse, hide_dead: bool) -> Vec<(StateID, Vec<(StateID, String)>)> {
+    let mut states: Vec<StateID> = dfa.transition_table.keys().copied().filter(|sid| *sid != dfa.dead_state).collect();
+    states.sort_unstable();
+    states.into_iter().map(|state| {
+        let mut row = [dfa.dead_state; 256];
+        for trans_desc in &dfa.transition_table[&state] {
+            let (lo, hi) = trans_desc.range();
+            row[lo as usize..=hi as usize].fill(trans_desc.next_state());
+        }
+        let mut targets: Vec<StateID> = Vec::new();
+        for next in row.into_iter().filter(|next| !hide_dead || *next != dfa.dead_state) {
+            if !targets.contains(&next) { targets.push(next); }
+        }
+        let edges = targets.into_iter().map(|next| (next, byte_class(&row.map(|target| target == next)))).collect();
+        (state, edges)
+    }).collect()
+}
+
+/// Label for a set of bytes: the byte itself, `.` for every byte, or a (negated, if shorter) class of ranges
+fn byte_class(bytes: &[bool; 256]) -> String {
+    let count = bytes.iter().filter(|byte| **byte).count();
+    if count == 256 { return ".".to_string() }
+    if count == 1 { return escape(bytes.iter().position(|byte| *byte).unwrap() as u8) }
+    let negated = count > 128;
+    let mut class = String::from(if negated { "[^" } else { "[" });
+    let mut byte = 0;
+    while byte < 256 {
+        if bytes[byte] == negated { byte += 1; continue }
+        let start = byte;
+        while byte < 256 && bytes[byte] != negated { byte += 1; }
+        class += &escape(start as u8);
+        if byte - start > 2 { class.push('-'); }
+        if byte - start > 1 { class += &escape((byte - 1) as u8); }
+    }
+    class + "]"
+}
+
+fn escape(byte: u8) -> String {
+    match byte {
+        b'\t' => "\\t".to_string(),
+        b'\n' => "\\n".to_string(),
+        b'\r' => "\\r".to_string(),
+        _ if META.contains(&byte) => format!("\\{}", byte as char),
+        0x21..=0x7e => (byte as char).to_string(),
+        _ => format!("\\x{:02x}", byte),
+    }
+}
+
+/// Whether any state has an edge into the dead state (so whether it needs drawing)
+fn reaches_dead(dfa: &Sparse, edges: &[(StateID, Vec<(StateID, String)>)]) -> bool {
+    edges.iter().any(|(_, state_edges)| state_edges.iter().any(|(next, _)| *next == dfa.dead_state))
+}
+
+pub(crate) fn dot(dfa: &Dfa, hide_dead: bool) -> String {
+    let dfa = dfa.sparse();
+    let edges = edges(dfa, hide_dead);
+    let quote = |label: &str| label.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut dot = String::from("digraph dfa {\n    rankdir=LR;\n    node [shape=circle];\n    start [shape=point];\n");
+    for (state, _) in &edges {
+        let shape = if dfa.match_states.contains(state) { " [shape=doublecircle]" } else { "" };
+        writeln!(dot, "    {}{};", state.as_usize(), shape).unwrap();
+    }
+    if reaches_dead(dfa, &edges) {
+        writeln!(dot, "    {} [label=\"dead\", style=dashed, color=gray, fontcolor=gray];", dfa.dead_state.as_usize()).unwrap();
+    }
+    writeln!(dot, "    start -> {};", dfa.start_state.as_usize()).unwrap();
+    for (state, state_edges) in &edges {
+        for (next, label) in state_edges {
+            let style = if *next == dfa.dead_state { ", style=dashed, color=gray, fontcolor=gray" } else { "" };
+            writeln!(dot, "    {} -> {} [label=\"{}\"{}];", state.as_usize(), next.as_usize(), quote(label), style).unwrap();
+        }
+    }
+    dot + "}\n"
+}
+
+pub(crate) fn mermaid(dfa: &Dfa, hide_dead: bool) -> String {
+    let dfa = dfa.sparse();
+    let edges = edges(dfa, hide_dead);
+    //Mermaid reads these as markup even inside quoted labels, so they go in as entity codes
+    let quote = |label: &str| label.bytes().map(|byte| match byte {
+        b'"' | b'#' | b'<' | b'>' | b'&' => format!("#{};", byte),
+        _ => (byte as char).to_string(),
+    }).collect::<String>();
+    let mut mermaid = String::from("flowchart LR\n    start((\" \")) --> s");
+    writeln!(mermaid, "{}", dfa.start_state.as_usize()).unwrap();
+    for (state, _) in &edges {
+        let id = state.as_usize();
+        if dfa.match_states.contains(state) { writeln!(mermaid, "    s{}((({})))", id, id).unwrap(); }
+        else { writeln!(mermaid, "    s{}(({}))", id, id).unwrap(); }
+    }
+    let draw_dead = reaches_dead(dfa, &edges);
+    if draw_dead { writeln!(mermaid, "    s{}[dead]:::dead", dfa.dead_state.as_usize()).unwrap(); }
+    for (state, state_edges) in &edges {
+        for (next, label) in state_edges {
+            writeln!(mermaid, "    s{} -->|\"{}\"| s{}", state.as_usize(), quote(label), next.as_usize()).unwrap();
+        }
+    }
+    if draw_dead { mermaid += "    classDef dead stroke-dasharray: 5 5, color: gray\n"; }
+    mermaid
+}
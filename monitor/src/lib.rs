@@ -1,9 +1,9 @@
 use std::{
     collections::{HashMap, HashSet}, 
-    fs::{create_dir, remove_dir_all, File}, 
+    fs::{self, File}, 
     io::{self, BufWriter, BufReader, Read, Write}, 
-    path::PathBuf,
-    u8::{MAX, MIN}
+    path::{Path, PathBuf},
+    sync::OnceLock,
 };
 use regex_automata::{
     dfa::{Automaton, StartError}, 
@@ -12,78 +12,231 @@ use regex_automata::{
         start::Config,
     }
 };
-use bitcode::{self, Encode, Decode};
-use serde;
-use serde_json;
+use bitcode::Decode;
+pub use adapters::{ValidatingReader, ValidatingWriter};
+#[cfg(feature = "async")]
+pub use async_io::{AsyncValidatingReader, ValidatedLines};
+use byte_dfa::ByteDfa;
+pub use cache::{Cache, CacheEntry, CACHE_DIR_VAR};
+use dense::{DenseTable, sparse_next_state};
+pub use error::{DfaDefect, LineRendering, MonitorError, ValidationFailure};
+pub use line_dfa::LineDfa;
+pub use sample::{Enumeration, NearMisses, Samples, Weighting};
+pub use stream::{CrlfMode, Framing, LineVerdict, StreamValidator};
 
 //Expose timer for use by any crate 
 pub mod timer;
+mod adapters;
+#[cfg(feature = "async")]
+mod async_io;
+mod byte_dfa;
+mod cache;
+mod compile;
+mod dense;
+mod error;
+mod graph;
+mod line_dfa;
+mod sample;
+mod stream;
+mod to_regex;
+mod validate;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TransitionDesc {
     Match(u8, StateID), //If an input byte == the sole u8 -> transition to StateID
     Range(u8, u8, StateID), //If an input byte is >= the first u8 and <= the second u8 -> transition to StateID
 }
+impl TransitionDesc {
+    pub fn next_state(&self) -> StateID {
+        match *self { TransitionDesc::Match(_, sid) | TransitionDesc::Range(_, _, sid) => sid }
+    }
+    /// Inclusive byte range accepted by this transition
+    pub fn range(&self) -> (u8, u8) {
+        match *self {
+            TransitionDesc::Match(byte, _) => (byte, byte),
+            TransitionDesc::Range(start_byte, end_byte, _) => (start_byte, end_byte),
+        }
+    }
+}
 pub type TransitionTable = HashMap<StateID, Vec<TransitionDesc>>;
 
+/// A DFA over bytes, described by a sparse `TransitionTable` and compiled into a dense, byte-class-compressed
+/// table for searching. The sparse form is the construction (and serialization) input; the `Automaton`
+/// implementation runs exclusively over the dense form, so the state IDs it hands out are *not* the IDs used in
+/// the sparse table (see `Dfa::dense_id`).
+///
+/// DFAs loaded from the flat serialized format start out with the dense table alone, and only work out their sparse
+/// form (numbering states by their row in the dense table) when first asked for it.
 pub struct Dfa {
+    sparse: OnceLock<Sparse>,
+    dense: DenseTable,
+}
+/// Sparse description of a `Dfa`
+struct Sparse {
     start_state: StateID,
     match_states: HashSet<StateID>,
     transition_table: TransitionTable,
     dead_state: StateID,
 }
 impl Dfa {
-    pub fn new(start_state: StateID, match_states: HashSet<StateID>, mut transition_table: TransitionTable) -> Self {
-        let dead_state = StateID::must(0);
-        //Check to make sure the transition table doesn't already define a state at dead state's ID and add dead state behavior
-        assert!(transition_table.get(&dead_state).is_none(), "Transition table already had behavior defined for StateID used as dead state");
+    /// Builds a DFA out of its sparse description, adding the dead state - whose ID (0) the transition table mustn't
+    /// define behavior for itself
+    pub fn new(start_state: StateID, match_states: HashSet<StateID>, transition_table: TransitionTable) -> Result<Self, Vec<DfaDefect>> {
+        if transition_table.contains_key(&StateID::ZERO) { return Err(vec![DfaDefect::DeadStateMisuse(StateID::ZERO, "has transitions leading out of it")]) }
+        Ok(Self::with_dead_state(start_state, match_states, transition_table))
+    }
+    /// `new` for transition tables known to leave the dead state's ID alone
+    pub(crate) fn with_dead_state(start_state: StateID, match_states: HashSet<StateID>, mut transition_table: TransitionTable) -> Self {
+        let dead_state = StateID::ZERO;
         transition_table.insert(
-            dead_state.clone(), 
-            vec![TransitionDesc::Range(MIN, MAX, dead_state.clone())]
+            dead_state, 
+            vec![TransitionDesc::Range(u8::MIN, u8::MAX, dead_state)]
         );
-        Dfa { start_state, match_states, transition_table, dead_state }
+        Self::from_parts(start_state, match_states, transition_table, dead_state)
     }
-    pub fn serialize(self) -> PathBuf { 
-        let serializable_self = SerDfa::from(self);
-        serializable_self.serialize().expect("Failed to serialize DFA")
+    /// Assembles a Dfa from an already complete sparse description (dead state included), compiling its dense table
+    fn from_parts(start_state: StateID, match_states: HashSet<StateID>, transition_table: TransitionTable, dead_state: StateID) -> Self {
+        let dense = DenseTable::compile(start_state, &match_states, &transition_table, dead_state);
+        Dfa { sparse: OnceLock::from(Sparse { start_state, match_states, transition_table, dead_state }), dense }
     }
-    pub fn deserialize(path: PathBuf) -> Self { 
-        let serializable_self = SerDfa::deserialize(&path).expect(format!("Failed to deserialize DFA from {:?}", path).as_str());
-        serializable_self.to_dfa()
+    fn sparse(&self) -> &Sparse {
+        self.sparse.get_or_init(|| {
+            let (start_state, match_states, transition_table) = self.dense.to_sparse();
+            Sparse { start_state, match_states, transition_table, dead_state: StateID::ZERO }
+        })
     }
-    pub fn deserialize_from_json(path: PathBuf) -> Self { dfa_from_json(path).expect("Failed deserializing DFA from JSON - check file path") }
-    pub fn clean_cache() { SerDfa::clean_ser_dir(); }
-}
-unsafe impl Automaton for Dfa {
-    fn next_state(&self, current: StateID, input: u8) -> StateID {
-        for trans_desc in self.transition_table.get(&current).expect("Current state not in transition table - misconstructed DFA") {
-            match trans_desc {
-                TransitionDesc::Match(byte, next_state) => { 
-                    if input == *byte { 
-                        //dbg!(format!("Next state: {:?}", next_state));
-                        return next_state.clone() 
-                    } 
-                },
-                TransitionDesc::Range(start_byte, end_byte, next_state) => {
-                    if input >= *start_byte && input <= *end_byte { 
-                        //dbg!(format!("Next state: {:?}", next_state));
-                        return next_state.clone() 
-                    }
-                },
-            }
+    /// Compiles a regular expression in dk.brics.automaton syntax (as taken by the old Java DFA builder) into a
+    /// minimal DFA that lines must match in full. See `compile::compile` for the supported syntax.
+    pub fn from_regex(regex: &str) -> Result<Self, MonitorError> {
+        compile::compile(regex).map_err(|reason| MonitorError::RegexCompile { regex: regex.to_string(), reason })
+    }
+    /// Equivalent DFA with the fewest states possible: unreachable states and states that can never lead to a match
+    /// are dropped, equivalent states are merged, and the survivors are renumbered densely from 1 (after the dead
+    /// state's 0) in breadth first order from the start state
+    pub fn minimize(&self) -> Self { ByteDfa::from_dfa(self).minimize().into_dfa() }
+    /// Minimal DFA matching the lines both DFAs match
+    pub fn intersect(&self, other: &Dfa) -> Self { ByteDfa::from_dfa(self).intersect(&ByteDfa::from_dfa(other)).minimize().into_dfa() }
+    /// Minimal DFA matching the lines either DFA matches
+    pub fn union(&self, other: &Dfa) -> Self { ByteDfa::from_dfa(self).union(&ByteDfa::from_dfa(other)).minimize().into_dfa() }
+    /// Minimal DFA matching the lines this DFA matches but the other doesn't
+    pub fn difference(&self, other: &Dfa) -> Self { ByteDfa::from_dfa(self).difference(&ByteDfa::from_dfa(other)).minimize().into_dfa() }
+    /// Minimal DFA matching every line (over all 256 byte values) this DFA doesn't
+    pub fn complement(&self) -> Self { ByteDfa::from_dfa(self).complement().into_dfa() }
+    /// Checks that every line this DFA matches, the other does too - failing with the shortest line that it doesn't
+    pub fn is_subset_of(&self, other: &Dfa) -> Result<(), Vec<u8>> {
+        match ByteDfa::from_dfa(self).difference(&ByteDfa::from_dfa(other)).shortest_match() { None => Ok(()), Some(line) => Err(line) }
+    }
+    /// Checks that both DFAs match the same lines - failing with the shortest line only one of them matches
+    pub fn equivalent(&self, other: &Dfa) -> Result<(), Vec<u8>> {
+        match ByteDfa::from_dfa(self).symmetric_difference(&ByteDfa::from_dfa(other)).shortest_match() { None => Ok(()), Some(line) => Err(line) }
+    }
+    /// Whether the DFA matches the line in full
+    pub fn matches(&self, line: &[u8]) -> bool {
+        let state = line.iter().fold(self.dense.start(), |state, byte| self.dense.next_state(state, *byte));
+        self.dense.is_match(state)
+    }
+    /// Every line the DFA matches of at most `max_len` bytes, shortest first and in byte order within a length
+    pub fn enumerate(&self, max_len: usize) -> Enumeration { Enumeration::new(self, max_len) }
+    /// Endless random lines the DFA matches of at most `max_len` bytes, reproducible from the seed
+    pub fn sample(&self, seed: u64, max_len: usize, weighting: Weighting) -> Samples { Samples::new(self, seed, max_len, weighting) }
+    /// Random lines the DFA rejects that are one edit (byte insertion, deletion or substitution) away from a sampled
+    /// line it matches
+    pub fn near_misses(&self, seed: u64, max_len: usize, weighting: Weighting) -> NearMisses { NearMisses::new(self, seed, max_len, weighting) }
+    /// Regular expression (in the syntax the monitor's `-r` takes) matching exactly the lines this DFA does, found by
+    /// state elimination over the minimized DFA
+    pub fn to_regex(&self) -> String { to_regex::to_regex(self) }
+    /// Graphviz DOT rendering of the DFA, with an edge per pair of states labelled with the bytes it takes (in
+    /// the syntax the monitor's `-r` takes) and match states double circled. Edges into the dead state are drawn dashed, or left out with
+    /// `hide_dead`.
+    pub fn to_dot(&self, hide_dead: bool) -> String { graph::dot(self, hide_dead) }
+    /// Mermaid flowchart rendering of the DFA, drawn like `to_dot`'s
+    pub fn to_mermaid(&self, hide_dead: bool) -> String { graph::mermaid(self, hide_dead) }
+    /// Serializes the DFA into the monitor's cache (see `Cache::locate`), returning the path it was written to
+    pub fn serialize(self) -> io::Result<PathBuf> { self.serialize_into(&Cache::locate(), None) }
+    /// Serializes the DFA into the given cache, recording what it was built from (eg. its regular expression) if given
+    pub fn serialize_into(self, cache: &Cache, source: Option<&str>) -> io::Result<PathBuf> {
+        let (bytes, hash) = SerDfa::from(&self).encode();
+        cache.store(&bytes, &hash, source)
+    }
+    /// Serializes the DFA to the given path rather than into the cache
+    pub fn serialize_to(self, path: &Path) -> io::Result<()> { SerDfa::from(&self).write(path) }
+    /// Serializes the DFA's dense table itself (see `dense::FLAT_MAGIC`), which `deserialize` loads without
+    /// rebuilding the DFA (and `deserialize_mapped` uses in place) - the quickest format to load, but several times
+    /// larger on disk and renumbering states by their row in the table
+    pub fn serialize_flat_to(&self, path: &Path) -> io::Result<()> { write_bytes(path, &self.dense.to_flat()) }
+    /// Writes the DFA out in the JSON form read by `deserialize_from_json`, with an optional comment describing it
+    pub fn serialize_to_json(&self, path: &Path, comment: Option<String>) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, &JsonDfa::from_dfa(self, comment)).map_err(io::Error::other)
+    }
+    /// Loads a serialized DFA in any of the formats (canonical, flat or pre-versioning), fully validating it. Flat
+    /// DFAs are also checked for transitions to states that don't exist and dead state misuse as they're read in.
+    pub fn deserialize(path: PathBuf) -> Result<Self, MonitorError> { 
+        let mut file = File::open(&path).map_err(|e| MonitorError::dfa_load(&path, e))?;
+        let mut magic = [0; 4];
+        if file.read_exact(&mut magic).is_ok() && &magic == dense::FLAT_MAGIC {
+            let bytes = fs::read(&path).map_err(|e| MonitorError::dfa_load(&path, e))?;
+            return Self::from_flat(path, DenseTable::from_flat_bytes(&bytes))
+        }
+        let serializable_self = SerDfa::deserialize(&path)?;
+        serializable_self.into_dfa().map_err(|defects| MonitorError::InvalidDfa { path, defects })
+    }
+    /// Loads a serialized DFA like `deserialize`, but maps a flat DFA into memory and uses its table in place rather
+    /// than copying it (DFAs in the other formats are loaded as `deserialize` does)
+    ///
+    /// # Safety
+    /// Lookups in a mapped table trust it once it has been checked, so nothing may change or truncate the file while
+    /// the DFA is alive - doing so is undefined behaviour. Only map files nothing else writes to.
+    pub unsafe fn deserialize_mapped(path: PathBuf) -> Result<Self, MonitorError> {
+        let mut file = File::open(&path).map_err(|e| MonitorError::dfa_load(&path, e))?;
+        let mut magic = [0; 4];
+        if file.read_exact(&mut magic).is_ok() && &magic == dense::FLAT_MAGIC {
+            //SAFETY: the caller guarantees nothing changes the file while the DFA (and so the mapping) is alive
+            let map = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| MonitorError::dfa_load(&path, e))?;
+            return Self::from_flat(path, unsafe { DenseTable::from_flat(map) })
         }
-        //dbg!(format!("Next state: {:?}", self.dead_state));
-        self.dead_state
+        Self::deserialize(path)
     }
+    /// Wraps a dense table read from a flat DFA, validating it as any other loaded DFA is
+    fn from_flat(path: PathBuf, dense: Result<DenseTable, String>) -> Result<Self, MonitorError> {
+        let dfa = Dfa { sparse: OnceLock::new(), dense: dense.map_err(|reason| MonitorError::dfa_load(&path, reason))? };
+        dfa.validate().map_err(|defects| MonitorError::InvalidDfa { path, defects })?;
+        Ok(dfa)
+    }
+    pub fn deserialize_from_json(path: PathBuf) -> Result<Self, MonitorError> { dfa_from_json(path) }
+    /// Removes every DFA from the monitor's cache
+    pub fn clean_cache() -> io::Result<()> { Cache::locate().clear() }
+    /// Checks the DFA's structure, listing every defect found (see `DfaDefect`) - run on every DFA loaded from a file
+    pub fn validate(&self) -> Result<(), Vec<DfaDefect>> {
+        let sparse = self.sparse();
+        let defects = validate::defects(sparse.start_state, &sparse.match_states, &sparse.transition_table, sparse.dead_state);
+        if defects.is_empty() { Ok(()) } else { Err(defects) }
+    }
+    /// Translates a state ID from the sparse transition table into the ID the `Automaton` implementation uses for it
+    pub fn dense_id(&self, sparse: StateID) -> Option<StateID> { self.dense.id(sparse) }
+    /// Number of states in the DFA (dead state included)
+    pub fn state_len(&self) -> usize { self.dense.state_len() }
+    /// Number of byte equivalence classes the dense table distinguishes between
+    pub fn alphabet_len(&self) -> usize { self.dense.alphabet_len() }
+    /// View of this DFA that searches by scanning the sparse transition table instead of the dense one. Slow - exists
+    /// as a reference implementation for testing and benchmarking.
+    pub fn as_sparse(&self) -> SparseDfa<'_> { SparseDfa(self) }
+}
+unsafe impl Automaton for Dfa {
+    #[inline(always)]
+    fn next_state(&self, current: StateID, input: u8) -> StateID { self.dense.next_state(current, input) }
+    #[inline(always)]
     unsafe fn next_state_unchecked(&self, current: StateID, input: u8) -> StateID {
-        self.next_state(current, input) //For now...
+        //SAFETY: callers of Automaton::next_state_unchecked guarantee current was produced by this automaton
+        unsafe { self.dense.next_state_unchecked(current, input) }
     }
     fn next_eoi_state(&self, current: StateID) -> StateID { current } //I think... I assume DFAs coming in will not have this special EOI feature
-    fn start_state(&self, _config: &Config) -> Result<StateID, StartError> { Ok(self.start_state) } //We don't need fancy configuration stuff - our searches are always anchored!
+    fn start_state(&self, _config: &Config) -> Result<StateID, StartError> { Ok(self.dense.start()) } //We don't need fancy configuration stuff - our searches are always anchored!
     fn is_special_state(&self, id: StateID) -> bool { self.is_dead_state(id) || self.is_match_state(id) || self.is_start_state(id) } //We can exclude quit and accel for now
-    fn is_dead_state(&self, id: StateID) -> bool { id == self.dead_state }
+    fn is_dead_state(&self, id: StateID) -> bool { id == StateID::ZERO } //The dead state always occupies the dense table's first row
     fn is_quit_state(&self, _id: StateID) -> bool { false } //I don't think we need quit states for our use case...
-    fn is_match_state(&self, id: StateID) -> bool { self.match_states.contains(&id) }
-    fn is_start_state(&self, id: StateID) -> bool { id == self.start_state }
+    fn is_match_state(&self, id: StateID) -> bool { self.dense.is_match(id) }
+    fn is_start_state(&self, id: StateID) -> bool { id == self.dense.start() }
     fn is_accel_state(&self, _id: StateID) -> bool { false } //For now...
     fn pattern_len(&self) -> usize { 1 } //The monitor should never search for more than one pattern
     fn match_len(&self, id: StateID) -> usize { 
@@ -94,49 +247,101 @@ unsafe impl Automaton for Dfa {
         //single pattern Automatons, so this should be good enough
         if self.is_match_state(id) { PatternID::must(0) } else { panic!("ID {:?} is not a match state", id) }
     }
-    fn has_empty(&self) -> bool { self.is_match_state(self.start_state) }
+    fn has_empty(&self) -> bool { self.is_match_state(self.dense.start()) }
     fn is_utf8(&self) -> bool { false } //Our automaton will only have to address ASCII characters
     fn is_always_start_anchored(&self) -> bool { true } //All patterns are anchored at both ends
 }
 
+/// Searches a `Dfa` by linearly scanning its sparse transition table for every input byte (the monitor's original
+/// search strategy). State IDs are the sparse table's own.
+pub struct SparseDfa<'a>(&'a Dfa);
+unsafe impl Automaton for SparseDfa<'_> {
+    fn next_state(&self, current: StateID, input: u8) -> StateID {
+        //States without a transition table entry have no transitions out of them
+        let trans_descs = self.0.sparse().transition_table.get(&current).map_or(&[][..], Vec::as_slice);
+        sparse_next_state(trans_descs, input).unwrap_or(self.0.sparse().dead_state)
+    }
+    unsafe fn next_state_unchecked(&self, current: StateID, input: u8) -> StateID { self.next_state(current, input) }
+    fn next_eoi_state(&self, current: StateID) -> StateID { current }
+    fn start_state(&self, _config: &Config) -> Result<StateID, StartError> { Ok(self.0.sparse().start_state) }
+    fn is_special_state(&self, id: StateID) -> bool { self.is_dead_state(id) || self.is_match_state(id) || self.is_start_state(id) }
+    fn is_dead_state(&self, id: StateID) -> bool { id == self.0.sparse().dead_state }
+    fn is_quit_state(&self, _id: StateID) -> bool { false }
+    fn is_match_state(&self, id: StateID) -> bool { self.0.sparse().match_states.contains(&id) }
+    fn is_start_state(&self, id: StateID) -> bool { id == self.0.sparse().start_state }
+    fn is_accel_state(&self, _id: StateID) -> bool { false }
+    fn pattern_len(&self) -> usize { 1 }
+    fn match_len(&self, id: StateID) -> usize { if self.is_match_state(id) { 1 } else { 0 } }
+    fn match_pattern(&self, id: StateID, _index: usize) -> PatternID { 
+        if self.is_match_state(id) { PatternID::must(0) } else { panic!("ID {:?} is not a match state", id) }
+    }
+    fn has_empty(&self) -> bool { self.is_match_state(self.0.sparse().start_state) }
+    fn is_utf8(&self) -> bool { false }
+    fn is_always_start_anchored(&self) -> bool { true }
+}
+
 //
 //SERIALIZATION OBJECTS
 //
 
-const CACHE_DIR: &str = "serialized-dfa-cache";
+/// Serialized DFAs start with these bytes, then the format version (a little endian u16), then the blake3 hash of the
+/// body that follows. Files without them are taken to be from before the format was versioned.
+const MAGIC: &[u8; 4] = b"SDFA";
+const FORMAT_VERSION: u16 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 2 + blake3::OUT_LEN;
 
-#[derive(Encode, Decode, Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Decode, Debug, PartialEq, Eq, Hash, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 enum STD {
     Match(u8, u32),
     Range(u8, u8, u32)
 }
+impl STD {
+    fn range(&self) -> (u8, u8) { match *self { STD::Match(b, _) => (b, b), STD::Range(b1, b2, _) => (b1, b2) } }
+    fn next_state(&self) -> u32 { match *self { STD::Match(_, sid) | STD::Range(_, _, sid) => sid } }
+}
 
-#[derive(Encode, Decode, Debug)]
+/// Canonical form of a DFA on disk: match states and states sorted by ID, each state's transitions by range start,
+/// so the same DFA always serializes to the same bytes
+#[derive(Debug)]
 struct SerDfa {
+    start_state: u32,
+    match_states: Vec<u32>,
+    transition_table: Vec<(u32, Vec<STD>)>,
+    dead_state: u32,
+}
+/// Unversioned bitcode encoding of a DFA, which depended on hash map iteration order - still read for compatibility
+#[derive(Decode)]
+struct LegacySerDfa {
     start_state: u32,
     match_states: HashSet<u32>,
     transition_table: HashMap<u32, Vec<STD>>,
     dead_state: u32,
 }
 impl SerDfa {
-    fn from(dfa: Dfa) -> Self {
-        let mut new_table = HashMap::new();
-        for (key, val) in dfa.transition_table {
+    fn from(dfa: &Dfa) -> Self {
+        let dfa = dfa.sparse();
+        let mut new_table = Vec::new();
+        for (key, val) in &dfa.transition_table {
             let new_val: Vec<STD> = val
                 .iter().map(|td| match *td {
                     TransitionDesc::Match(b, sid) => STD::Match(b, sid.as_u32()),
                     TransitionDesc::Range(b1, b2, sid) => STD::Range(b1, b2, sid.as_u32())
                 }).collect();
-            new_table.insert(key.as_u32(), new_val);
-        }
-        Self {
-            start_state: dfa.start_state.as_u32(),
-            match_states: dfa.match_states.iter().map(|sid| sid.as_u32()).collect(),
-            transition_table: new_table,
-            dead_state: dfa.dead_state.as_u32(),
+            new_table.push((key.as_u32(), new_val));
         }
+        Self::canonical(dfa.start_state.as_u32(), dfa.match_states.iter().map(|sid| sid.as_u32()).collect(), new_table, dfa.dead_state.as_u32())
     }
-    fn to_dfa(self) -> Dfa {
+    fn canonical(start_state: u32, mut match_states: Vec<u32>, mut transition_table: Vec<(u32, Vec<STD>)>, dead_state: u32) -> Self {
+        match_states.sort_unstable();
+        transition_table.sort_unstable_by_key(|(key, _)| *key);
+        for (_, val) in transition_table.iter_mut() { val.sort_by_key(STD::range); }
+        SerDfa { start_state, match_states, transition_table, dead_state }
+    }
+    fn into_dfa(self) -> Result<Dfa, Vec<DfaDefect>> {
+        let targets = self.transition_table.iter().flat_map(|(key, val)| std::iter::once(*key).chain(val.iter().map(STD::next_state)));
+        let ids = [self.start_state, self.dead_state].into_iter().chain(self.match_states.iter().copied()).chain(targets);
+        check_state_ids(ids.map(|sid| sid as usize))?;
         let mut new_table: TransitionTable = HashMap::new();
         for (key, val) in self.transition_table {
             let new_val: Vec<TransitionDesc> = val
@@ -146,58 +351,130 @@ impl SerDfa {
                 }).collect();
             new_table.insert(StateID::must(key as usize), new_val);
         }
-        Dfa {
-            start_state: StateID::must(self.start_state as usize), 
-            match_states: self.match_states.iter().map(|sid| StateID::must(*sid as usize)).collect(),
-            transition_table: new_table,
-            dead_state: StateID::must(self.dead_state as usize),
+        let start_state = StateID::must(self.start_state as usize);
+        let match_states = self.match_states.iter().map(|sid| StateID::must(*sid as usize)).collect();
+        let dead_state = StateID::must(self.dead_state as usize);
+        //Validated before the dense table gets compiled out of it
+        let defects = validate::defects(start_state, &match_states, &new_table, dead_state);
+        if !defects.is_empty() { return Err(defects) }
+        Ok(Dfa::from_parts(start_state, match_states, new_table, dead_state))
+    }
+    /// Body of the serialized form: little endian u32s for the start state, dead state, match state count and match
+    /// states, then the state count and per state its ID, transition count and transitions (range start and end
+    /// bytes, then target)
+    fn body(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        let put = |body: &mut Vec<u8>, n: usize| body.extend_from_slice(&(n as u32).to_le_bytes());
+        put(&mut body, self.start_state as usize);
+        put(&mut body, self.dead_state as usize);
+        put(&mut body, self.match_states.len());
+        for sid in &self.match_states { put(&mut body, *sid as usize); }
+        put(&mut body, self.transition_table.len());
+        for (key, val) in &self.transition_table {
+            put(&mut body, *key as usize);
+            put(&mut body, val.len());
+            for td in val {
+                let (b1, b2) = td.range();
+                body.extend_from_slice(&[b1, b2]);
+                put(&mut body, td.next_state() as usize);
+            }
         }
+        body
+    }
+    fn from_body(body: &[u8]) -> Option<Self> {
+        let mut reader = BodyReader(body);
+        let start_state = reader.u32()?;
+        let dead_state = reader.u32()?;
+        let match_states = (0..reader.u32()?).map(|_| reader.u32()).collect::<Option<Vec<u32>>>()?;
+        let mut transition_table = Vec::new();
+        for _ in 0..reader.u32()? {
+            let key = reader.u32()?;
+            let mut val = Vec::new();
+            for _ in 0..reader.u32()? {
+                let [b1, b2] = reader.take()?;
+                let sid = reader.u32()?;
+                val.push(if b1 == b2 { STD::Match(b1, sid) } else { STD::Range(b1, b2, sid) });
+            }
+            transition_table.push((key, val));
+        }
+        if !reader.0.is_empty() { return None }
+        Some(SerDfa { start_state, match_states, transition_table, dead_state })
+    }
+    /// Header and body of the serialized form, along with the body's hash
+    fn encode(&self) -> (Vec<u8>, blake3::Hash) {
+        let body = self.body();
+        let hash = blake3::hash(&body);
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(hash.as_bytes());
+        bytes.extend_from_slice(&body);
+        (bytes, hash)
     }
-    fn serialize(&self) -> io::Result<PathBuf> {
-        //Encode into bytes
-        let bytes: Vec<u8> = bitcode::encode(self);
-        //Create file with name based on hash of encoded bytes - WARNING: file names are non-deterministic due to non-deterministic iterating order of hashmaps/hashsets
-        let hash = blake3::hash(&bytes);
-        let cache_dir = proj_root().join(CACHE_DIR);
-        if !cache_dir.exists() { create_dir(&cache_dir).expect("Failed to create dfa cache dir"); }
-        let path = cache_dir.join(format!("sdfa-{}.bc", hash.to_hex()[..8].to_string()));
-        let file = File::create(&path).expect(format!("Couldn't create file at path: {:?}", path).as_str());
-        //Write bytes to file
-        let mut writer = BufWriter::new(file);
-        writer.write_all(&bytes)?;
-        writer.flush()?;
-        Ok(path)
-    }
-    fn deserialize(path: &PathBuf) -> io::Result<Self> {
-        let file = File::open(path).expect(format!("Couldn't open file at path: {:?}", path).as_str());
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let Some(rest) = bytes.strip_prefix(MAGIC) else {
+            let legacy: LegacySerDfa = bitcode::decode(bytes).map_err(|e| format!("not a serialized DFA ({})", e))?;
+            //These leave out the table entries of states without transitions - as in JSON descriptions, a state that's
+            //referenced at all exists
+            let mut table = legacy.transition_table;
+            let referenced: Vec<u32> = table.values().flatten().map(STD::next_state).chain([legacy.start_state]).collect();
+            for sid in referenced { table.entry(sid).or_default(); }
+            let table = table.into_iter().collect();
+            return Ok(Self::canonical(legacy.start_state, legacy.match_states.into_iter().collect(), table, legacy.dead_state))
+        };
+        let version = rest.get(..2).map(|version| u16::from_le_bytes(version.try_into().unwrap()));
+        match version {
+            Some(FORMAT_VERSION) => {},
+            Some(version) => return Err(format!(
+                "serialized DFA format version {} is not supported (this monitor reads version {}) - re-serialize it with this monitor", version, FORMAT_VERSION
+            )),
+            None => return Err("serialized DFA is truncated".to_string()),
+        }
+        if bytes.len() < HEADER_LEN { return Err("serialized DFA is truncated".to_string()) }
+        let body = &bytes[HEADER_LEN..];
+        if blake3::hash(body).as_bytes() != &bytes[MAGIC.len() + 2..HEADER_LEN] { return Err("serialized DFA is corrupt (its content hash doesn't match)".to_string()) }
+        Self::from_body(body).ok_or_else(|| "serialized DFA is corrupt (malformed body)".to_string())
+    }
+    fn write(&self, path: &Path) -> io::Result<()> { write_bytes(path, &self.encode().0) }
+    fn deserialize(path: &PathBuf) -> Result<Self, MonitorError> {
+        let file = File::open(path).map_err(|e| MonitorError::dfa_load(path, e))?;
         let mut reader = BufReader::new(file);
         let mut buffer = Vec::new();
-        reader.read_to_end(&mut buffer)?;
-        Ok(bitcode::decode(&buffer).expect("Failed to decode"))
+        reader.read_to_end(&mut buffer).map_err(|e| MonitorError::dfa_load(path, e))?;
+        Self::decode(&buffer).map_err(|reason| MonitorError::dfa_load(path, reason))
     }
-    fn clean_ser_dir() {
-        let cache_dir = proj_root().join(CACHE_DIR);
-        if cache_dir.exists() { 
-            remove_dir_all(cache_dir).expect("Failed to delete serialized DFA cache directory"); 
-        }
+}
+
+/// Reads a serialized DFA's body front to back, failing once it runs out
+struct BodyReader<'a>(&'a [u8]);
+impl BodyReader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (bytes, rest) = self.0.split_first_chunk()?;
+        self.0 = rest;
+        Some(*bytes)
     }
+    fn u32(&mut self) -> Option<u32> { self.take().map(u32::from_le_bytes) }
 }
 
-fn proj_root() -> PathBuf {
-    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+fn write_bytes(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(bytes)?;
+    writer.flush()
 }
 
 //
 //JSON PARSING OBJECTS
 //
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 struct JsonDfa {
+    #[serde(rename = "_comment", default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
     start_state: usize, //Unfortunately, there's no way to get serde to deserialize directly to StateIDs because StateIDs have private fields
     match_states: Vec<usize>,
     transition_table: Vec<JsonTransition>,
 }
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 struct JsonTransition {
     curr_state: usize,
     range_start: u8,
@@ -205,16 +482,58 @@ struct JsonTransition {
     next_state: usize, 
 }
 
+impl JsonDfa {
+    /// JSON form of a DFA, with states and transitions sorted so the output is deterministic
+    fn from_dfa(dfa: &Dfa, comment: Option<String>) -> Self {
+        let dfa = dfa.sparse();
+        let mut match_states: Vec<usize> = dfa.match_states.iter().map(|sid| sid.as_usize()).collect();
+        match_states.sort_unstable();
+        let mut transition_table: Vec<JsonTransition> = dfa.transition_table.iter()
+            .filter(|(sid, _)| **sid != dfa.dead_state)
+            .flat_map(|(sid, trans_descs)| trans_descs.iter().map(|td| {
+                let (range_start, range_end) = td.range();
+                JsonTransition { curr_state: sid.as_usize(), range_start, range_end, next_state: td.next_state().as_usize() }
+            }))
+            .collect();
+        transition_table.sort_unstable_by_key(|trans| (trans.curr_state, trans.range_start));
+        JsonDfa { comment, start_state: dfa.start_state.as_usize(), match_states, transition_table }
+    }
+    fn into_dfa(self) -> Result<Dfa, Vec<DfaDefect>> { dfa_from_json_parts(self.start_state, &self.match_states, self.transition_table) }
+}
+
+/// Builds and validates a Dfa out of the parts of a JSON description. A state exists by being mentioned at all, but
+/// the dead state's ID (0) is reserved - `Dfa::new` defines its behavior.
+fn dfa_from_json_parts(start_state: usize, match_states: &[usize], transitions: Vec<JsonTransition>) -> Result<Dfa, Vec<DfaDefect>> {
+    let targets = transitions.iter().flat_map(|trans| [trans.curr_state, trans.next_state]);
+    check_state_ids(std::iter::once(start_state).chain(match_states.iter().copied()).chain(targets))?;
+    let dead_state = StateID::ZERO;
+    let mut defects = Vec::new();
+    if start_state == 0 { defects.push(DfaDefect::DeadStateMisuse(dead_state, "is the start state")); }
+    if match_states.contains(&0) { defects.push(DfaDefect::DeadStateMisuse(dead_state, "is a match state")); }
+    if transitions.iter().any(|trans| trans.curr_state == 0) { defects.push(DfaDefect::DeadStateMisuse(dead_state, "has transitions leading out of it")); }
+    if transitions.iter().any(|trans| trans.next_state == 0) { defects.push(DfaDefect::DeadStateMisuse(dead_state, "is the target of a transition")); }
+    if !defects.is_empty() { return Err(defects) }
+    let mut transition_table = convert_json_transitions(transitions);
+    transition_table.entry(StateID::must(start_state)).or_default();
+    let dfa = Dfa::with_dead_state(StateID::must(start_state), match_states.iter().map(|sid| StateID::must(*sid)).collect(), transition_table);
+    dfa.validate()?;
+    Ok(dfa)
+}
+
+/// Checks that every state ID read from a file fits in a `StateID`
+fn check_state_ids(ids: impl Iterator<Item = usize>) -> Result<(), Vec<DfaDefect>> {
+    let mut too_large: Vec<usize> = ids.filter(|sid| StateID::new(*sid).is_err()).collect();
+    too_large.sort_unstable();
+    too_large.dedup();
+    if too_large.is_empty() { Ok(()) } else { Err(too_large.into_iter().map(DfaDefect::StateIdTooLarge).collect()) }
+}
+
 /// Given a path to a JSON file containing properly formatted information about a DFA,
 /// attempts to construct a Dfa object out of that information
-fn dfa_from_json(json_path: PathBuf) -> std::io::Result<Dfa> { 
-    let json_file = File::open(json_path)?;
-    let json_dfa: JsonDfa = serde_json::from_reader(BufReader::new(json_file))?;
-    Ok(Dfa::new(
-        StateID::must(json_dfa.start_state),
-        json_dfa.match_states.iter().map(|sid| StateID::must(*sid)).collect::<HashSet<StateID>>(),
-        convert_json_transitions(json_dfa.transition_table)
-    ))
+fn dfa_from_json(json_path: PathBuf) -> Result<Dfa, MonitorError> { 
+    let json_file = File::open(&json_path).map_err(|e| MonitorError::dfa_load(&json_path, e))?;
+    let json_dfa: JsonDfa = serde_json::from_reader(BufReader::new(json_file)).map_err(|e| MonitorError::dfa_load(&json_path, e))?;
+    json_dfa.into_dfa().map_err(|defects| MonitorError::InvalidDfa { path: json_path, defects })
  }
 
  fn convert_json_transitions(jtrans: Vec<JsonTransition>) -> TransitionTable {
@@ -226,17 +545,17 @@ fn dfa_from_json(json_path: PathBuf) -> std::io::Result<Dfa> {
             .entry(StateID::must(trans.curr_state)) 
             .or_insert(Vec::new());
         let next_state = StateID::must(trans.next_state);
-        next_states.insert(next_state.clone());
+        next_states.insert(next_state);
         let new_trans_desc = if trans.range_start == trans.range_end { TransitionDesc::Match(trans.range_start, next_state) } 
             else { TransitionDesc::Range(trans.range_start, trans.range_end, next_state) };
         trans_desc_vec.push(new_trans_desc);
     }
+    //JSON transitions may be listed in any order
+    for trans_descs in trans_table.values_mut() { trans_descs.sort_by_key(|td: &TransitionDesc| td.range().0); }
     //Check to make sure that all states do actually have an entry in the table - this is not garaunteed be default!
     let next_states: Vec<StateID> = next_states.into_iter().collect();
     for state in next_states {
-        if let None = trans_table.get(&state) { 
-            trans_table.insert(state, Vec::new()); //Empty vector because all transitions from these states lead to dead state
-        }
+        trans_table.entry(state).or_insert_with(Vec::new); //Empty vector because all transitions from these states lead to dead state
     }
     trans_table
  }
\ No newline at end of file
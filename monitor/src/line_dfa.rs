@@ -0,0 +1,110 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+use regex_automata::{
+    dfa::Automaton,
+    util::primitives::StateID,
+};
+use crate::{dfa_from_json, dfa_from_json_parts, Dfa, JsonDfa, JsonTransition, MonitorError, TransitionTable};
+
+/// A regular type over the *lines* of a stream. Each line is classified by a set of named per-line DFAs (its line
+/// classes), and an outer DFA whose alphabet is the classes' indices validates the sequence of classes.
+///
+/// Only the classes the outer DFA can currently transition on are tried against a line; if the line matches several
+/// of them, the first in declaration order wins.
+pub struct LineDfa {
+    class_names: Vec<String>,
+    class_dfas: Vec<Dfa>,
+    outer: Dfa,
+}
+impl LineDfa {
+    /// Builds a line DFA out of its (at most 256) named line classes and the outer DFA's description, whose
+    /// transitions consume the index of a class in place of a byte
+    pub fn new(classes: Vec<(String, Dfa)>, start_state: StateID, match_states: HashSet<StateID>, transition_table: TransitionTable) -> Result<Self, MonitorError> {
+        if classes.len() > 256 { return Err(MonitorError::Usage(String::from("Line DFAs support at most 256 line classes"))) }
+        let outer = Dfa::new(start_state, match_states, transition_table).map_err(|defects| {
+            let defects: Vec<String> = defects.iter().map(|defect| defect.to_string()).collect();
+            MonitorError::Usage(format!("Line DFA's outer DFA is invalid: {}", defects.join("; ")))
+        })?;
+        Ok(Self::from_parts(classes, outer))
+    }
+    /// Assembles a line DFA out of at most 256 classes
+    fn from_parts(classes: Vec<(String, Dfa)>, outer: Dfa) -> Self {
+        let (class_names, class_dfas) = classes.into_iter().unzip();
+        LineDfa { class_names, class_dfas, outer }
+    }
+    pub fn deserialize_from_json(path: PathBuf) -> Result<Self, MonitorError> { line_dfa_from_json(path) }
+    pub fn class_len(&self) -> usize { self.class_names.len() }
+    pub fn class_name(&self, class: usize) -> &str { &self.class_names[class] }
+    pub fn class_dfa(&self, class: usize) -> &Dfa { &self.class_dfas[class] }
+    /// The DFA over line classes - its `Automaton` implementation takes class indices as input bytes
+    pub fn outer(&self) -> &Dfa { &self.outer }
+    pub fn start_state(&self) -> StateID { self.outer.dense.start() }
+    /// Classes the next line may belong to when the outer DFA is in the given state
+    pub fn expected(&self, state: StateID) -> Vec<usize> {
+        (0..self.class_len()).filter(|class| !self.outer.is_dead_state(self.outer.next_state(state, *class as u8))).collect()
+    }
+    /// Moves the outer DFA past a line of the given class
+    pub fn next_state(&self, state: StateID, class: usize) -> StateID { self.outer.next_state(state, class as u8) }
+    /// Whether the stream may end with the outer DFA in the given state
+    pub fn is_accepting(&self, state: StateID) -> bool { self.outer.is_match_state(self.outer.next_eoi_state(state)) }
+}
+
+//
+//JSON PARSING OBJECTS
+//
+
+#[derive(serde::Deserialize)]
+struct JsonLineDfa {
+    classes: Vec<JsonLineClass>,
+    start_state: usize,
+    match_states: Vec<usize>,
+    transition_table: Vec<JsonClassTransition>,
+}
+#[derive(serde::Deserialize)]
+struct JsonLineClass {
+    name: String,
+    dfa: Option<JsonDfa>, //The class's DFA, inline...
+    path: Option<PathBuf>, //...or as a path to a JSON DFA file, relative to the line DFA's own file
+}
+#[derive(serde::Deserialize)]
+struct JsonClassTransition {
+    curr_state: usize,
+    class: String,
+    next_state: usize,
+}
+
+/// Given a path to a JSON file containing properly formatted information about a line DFA, attempts to construct
+/// a LineDfa object out of that information
+fn line_dfa_from_json(json_path: PathBuf) -> Result<LineDfa, MonitorError> {
+    let json_file = File::open(&json_path).map_err(|e| MonitorError::dfa_load(&json_path, e))?;
+    let json_line_dfa: JsonLineDfa = serde_json::from_reader(BufReader::new(json_file)).map_err(|e| MonitorError::dfa_load(&json_path, e))?;
+    if json_line_dfa.classes.len() > 256 { return Err(MonitorError::dfa_load(&json_path, "line DFAs support at most 256 line classes")) }
+    let base_dir = json_path.parent().unwrap_or(Path::new("."));
+    let mut classes = Vec::new();
+    for class in json_line_dfa.classes {
+        let dfa = match (class.dfa, class.path) {
+            (Some(json_dfa), None) => json_dfa.into_dfa().map_err(|defects| {
+                let defects: Vec<String> = defects.iter().map(|defect| defect.to_string()).collect();
+                MonitorError::dfa_load(&json_path, format!("line class {:?} is invalid: {}", class.name, defects.join("; ")))
+            })?,
+            (None, Some(path)) => dfa_from_json(base_dir.join(path))?,
+            _ => return Err(MonitorError::dfa_load(&json_path, format!("line class {:?} needs exactly one of dfa or path", class.name))),
+        };
+        classes.push((class.name, dfa));
+    }
+    //Outer transitions consume class indices, so they become single byte transitions
+    let mut transitions = Vec::new();
+    for trans in json_line_dfa.transition_table {
+        let Some(class) = classes.iter().position(|(name, _)| *name == trans.class) else {
+            return Err(MonitorError::dfa_load(&json_path, format!("transition on unknown line class {:?}", trans.class)))
+        };
+        transitions.push(JsonTransition { curr_state: trans.curr_state, range_start: class as u8, range_end: class as u8, next_state: trans.next_state });
+    }
+    let outer = dfa_from_json_parts(json_line_dfa.start_state, &json_line_dfa.match_states, transitions)
+        .map_err(|defects| MonitorError::InvalidDfa { path: json_path.clone(), defects })?;
+    Ok(LineDfa::from_parts(classes, outer))
+}
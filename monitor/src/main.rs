@@ -1,14 +1,24 @@
-use clap::Parser;
-use regex_automata::{ dfa::{dense::DFA, Automaton}, HalfMatch, Input };
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use regex_automata::{ dfa::{dense::DFA, Automaton}, nfa::thompson, util::syntax };
 use atty::{self, Stream};
 use std::path::PathBuf;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Write};
 use std::process::exit;
 use std::fs::{self, File};
 use std::env;
+use std::time::{Duration, SystemTime};
 use nix::sys::signal::{self, Signal};
 use nix::unistd::Pid;
-use monitor::Dfa;
+use monitor::{Cache, CrlfMode, Dfa, Framing, LineDfa, LineRendering, LineVerdict, MonitorError, StreamValidator, Weighting};
+
+mod classes;
+mod fields;
+mod run;
+mod sections;
+use classes::validate_line_classes;
+use fields::{Fields, validate_fields};
+use run::{parse_grace, parse_signal, run_wrapped, FailPolicy, Stop, StreamType, StreamTypes};
+use sections::{LineCount, Section, validate_sections};
 
 #[cfg(test)]
 mod tests;
@@ -18,99 +28,696 @@ mod tests;
 //Figure out how input validation should work
 //Figure out how to handle errors that crop up from running input commands themselves
 
+const EXIT_CODES: &str = "Exit codes:
+  0  Stream validated
+  1  Stream did not match its type (for check, the DFAs are not related as asked)
+  2  Invalid arguments
+  3  DFA could not be loaded
+  4  Regular expression could not be compiled
+  5  I/O error reading the input stream
+  6  Trap (-t) not properly set up
+For run, a command whose output validated passes on its own exit code instead of 0.";
+
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, after_help = EXIT_CODES, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     ///File path to serialized DFA - if not specified, regex or no_validation must be set
     #[arg(short, required(false))]
     dfa_path: Option<PathBuf>,
+    ///Memory map a DFA given with -d in the flat format and use it in place rather than copying it into memory. Nothing
+    ///may change or truncate the file while the monitor runs - only use it for files nothing else writes to.
+    #[arg(long, default_value_t=false, requires = "dfa_path")]
+    mmap: bool,
     ///Regular expression for validation instead of DFA
     #[arg(short, required(false))]
     regex: Option<String>,
+    ///File path to a JSON line DFA - named line classes that each line is classified by, and a DFA over those classes
+    ///that the sequence of lines must match
+    #[arg(short, long, value_name = "PATH", conflicts_with_all = ["dfa_path", "regex", "no_validation", "header", "body", "footer"])]
+    line_dfa: Option<PathBuf>,
     ///No validation will be performed (DFA defaults to a .* matcher) - mainly exists for development purposes
     #[arg(long, default_value_t=false)]
     no_validation: bool,
-    ///On a failed validation, instead of panicking, will send a SIGTERM signal to the PID stored at env variable
-    ///MONITOR_TARGET_PID
+    ///On a failed validation, instead of printing the failure to stderr, will send a SIGUSR1 signal to the PID stored
+    ///at env variable MONITOR_TARGET_PID
     #[arg(short, long, default_value_t=false)]
     trap: bool,
+    ///Instead of stopping at the first line that fails validation, keep streaming (passing along only valid lines),
+    ///then print a summary of every failing line at EOF and exit with a non-zero status
+    #[arg(long, default_value_t=false)]
+    report_all: bool,
+    ///Render offending lines in error messages lossily (invalid UTF-8 replaced with U+FFFD) instead of escaping
+    ///invalid bytes as \xNN
+    #[arg(long, default_value_t=false)]
+    lossy: bool,
+    ///Records are delimited by NUL bytes instead of newlines (eg. for find -print0 output)
+    #[arg(short = 'z', long, default_value_t=false, conflicts_with = "delimiter")]
+    null_data: bool,
+    ///Byte that records are delimited by instead of newlines - a single ASCII character, an escape (\t, \n, \r, \0) or
+    ///a hex byte (0xNN or \xNN)
+    #[arg(long, value_name = "BYTE", value_parser = parse_delimiter)]
+    delimiter: Option<u8>,
+    ///How a \r directly before each delimiter is handled: strip leaves it out of validation wherever present (it's
+    ///still passed to the output), require fails any record without one and keep validates it like any other byte
+    #[arg(long, value_name = "MODE", default_value = "strip")]
+    crlf: Crlf,
+    ///Type of the stream's header, which must precede the body: dfa:PATH (serialized DFA), json:PATH (JSON DFA) or
+    ///regex:PATTERN (a bare value is treated as a regex)
+    #[arg(long, value_name = "TYPE")]
+    header: Option<String>,
+    ///Type of the stream's body, in the same form as --header - alternatively, -d or -r give the body's type when
+    ///a header or footer is specified
+    #[arg(long, value_name = "TYPE", conflicts_with_all = ["dfa_path", "regex", "no_validation"])]
+    body: Option<String>,
+    ///Type of the stream's footer, which must follow the body, in the same form as --header
+    #[arg(long, value_name = "TYPE")]
+    footer: Option<String>,
+    ///Number of lines the header spans: N, N.., ..M or N..M (bounds inclusive)
+    #[arg(long, value_name = "COUNT", default_value = "1", value_parser = LineCount::parse)]
+    header_lines: LineCount,
+    ///Number of lines the body spans, in the same form as --header-lines
+    #[arg(long, value_name = "COUNT", default_value = "0..", value_parser = LineCount::parse)]
+    body_lines: LineCount,
+    ///Number of lines the footer spans, in the same form as --header-lines
+    #[arg(long, value_name = "COUNT", default_value = "1", value_parser = LineCount::parse)]
+    footer_lines: LineCount,
+    ///Type of a column of each line, in the same form as --header - repeat for each column in order. Lines are split
+    ///on runs of whitespace unless --field-separator is given.
+    #[arg(long, value_name = "TYPE", conflicts_with_all = ["dfa_path", "regex", "no_validation", "line_dfa", "header", "body", "footer"])]
+    field: Vec<String>,
+    ///Type of whatever follows the --field columns, left unsplit (validated as empty if the line has no more columns)
+    #[arg(long, value_name = "TYPE", conflicts_with_all = ["dfa_path", "regex", "no_validation", "line_dfa", "header", "body", "footer"])]
+    rest: Option<String>,
+    ///Byte that columns are separated by, in the same form as --delimiter
+    #[arg(long, value_name = "BYTE", value_parser = parse_delimiter)]
+    field_separator: Option<u8>,
+    ///Number of columns each line must have, in the same form as --header-lines (defaults to the number of --field
+    ///options) - columns past the last --field share its type
+    #[arg(long, value_name = "COUNT", value_parser = LineCount::parse, requires = "field", conflicts_with = "rest")]
+    columns: Option<LineCount>,
     ///File path to file containing input to check - if not specified, monitor will instead look to stdin
     #[arg(required(false))]
     input_file: Option<PathBuf>,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    ///Compile a regular expression in dk.brics.automaton syntax (eg. ~ for complement, & for intersection) into a
+    ///minimized DFA that whole lines must match, printing the path of the serialized DFA
+    Compile {
+        ///Regular expression to compile
+        regex: String,
+        ///Path to write the serialized DFA to - defaults to the DFA cache
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        ///Also write the DFA in JSON form to this path
+        #[arg(long, value_name = "PATH")]
+        json: Option<PathBuf>,
+        ///Write the output in the flat format, which loads fastest (used in place with the monitor's --mmap) but takes
+        ///more space
+        #[arg(long, requires = "output")]
+        flat: bool,
+    },
+    ///Combine DFAs (serialized, or JSON if given as json:PATH or a .json file) into a new minimized DFA, printing the
+    ///path of the serialized result
+    Dfa {
+        ///Operation to perform - complement takes a single DFA, the rest two
+        operation: DfaOperation,
+        ///DFA to operate on
+        dfa: String,
+        ///Second DFA for binary operations (for difference, the lines to remove from the first DFA's)
+        other: Option<String>,
+        ///Path to write the serialized DFA to - defaults to the DFA cache
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+        ///Also write the DFA in JSON form to this path
+        #[arg(long, value_name = "PATH")]
+        json: Option<PathBuf>,
+        ///Write the output in the flat format, which loads fastest (used in place with the monitor's --mmap) but takes
+        ///more space
+        #[arg(long, requires = "output")]
+        flat: bool,
+    },
+    ///Check whether every line the first DFA matches, the second does too (subset) or whether both match the same
+    ///lines (equivalent), printing the shortest counterexample line if not. DFAs are given as for the dfa subcommand.
+    Check {
+        ///Relation to check
+        relation: Relation,
+        ///First DFA (for subset, the one that should match fewer lines)
+        dfa: String,
+        ///Second DFA
+        other: String,
+    },
+    ///Print random lines a DFA (given as for the dfa subcommand) matches, for testing consumers of monitored streams.
+    ///Lines never contain the delimiter they are printed with.
+    Sample {
+        ///DFA to generate lines from
+        dfa: String,
+        ///Number of lines to print
+        #[arg(short = 'n', long, default_value_t = 10)]
+        count: usize,
+        ///Maximum length of the lines in bytes
+        #[arg(long, default_value_t = 64)]
+        max_len: usize,
+        ///Seed for reproducible output - defaults to a random one
+        #[arg(long, conflicts_with = "enumerate")]
+        seed: Option<u64>,
+        ///Make every line length equally likely, rather than every line (which favours the longest lines)
+        #[arg(long, conflicts_with = "enumerate")]
+        by_length: bool,
+        ///Print the lines the DFA rejects that are one byte inserted, deleted or substituted away from ones it matches
+        #[arg(long, conflicts_with = "enumerate")]
+        near_misses: bool,
+        ///Print the shortest lines the DFA matches in order instead of random ones
+        #[arg(long)]
+        enumerate: bool,
+        ///Byte to end each line with, in the same form as the monitor's --delimiter
+        #[arg(long, value_name = "BYTE", value_parser = parse_delimiter, default_value = "\\n")]
+        delimiter: u8,
+    },
+    ///Print a regular expression matching exactly the lines a DFA (given as for the dfa subcommand) does, in the syntax
+    ///-r takes
+    Regex {
+        ///DFA to convert
+        dfa: String,
+    },
+    ///Draw a DFA (given as for the dfa subcommand) as a Graphviz DOT or Mermaid graph, with edges labelled in the
+    ///syntax -r takes
+    Graph {
+        ///DFA to draw
+        dfa: String,
+        ///Graph language to write
+        #[arg(short, long, default_value = "dot")]
+        format: GraphFormat,
+        ///Leave out the dead state and the edges into it
+        #[arg(long)]
+        hide_dead: bool,
+        ///Path to write the graph to - defaults to stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    ///Run a command, interposing on its standard streams: each one given a type is validated a line at a time, input
+    ///before it's passed on to the command and output before it's emitted, like the monitor does a piped stream. By
+    ///default the command is stopped at the first violation; otherwise the monitor exits with the command's own status.
+    #[command(group(ArgGroup::new("types").required(true).multiple(true).args(["stdin_type", "stdout_type", "stderr_type"])))]
+    Run {
+        ///Type of the input passed on to the command: dfa:PATH (serialized DFA), json:PATH (JSON DFA) or regex:PATTERN
+        ///(a bare value is treated as a regex)
+        #[arg(long, value_name = "TYPE")]
+        stdin_type: Option<String>,
+        ///Type of the command's stdout, in the same form as --stdin-type
+        #[arg(long, visible_alias = "type", value_name = "TYPE")]
+        stdout_type: Option<String>,
+        ///Type of the command's stderr, in the same form as --stdin-type
+        #[arg(long, value_name = "TYPE")]
+        stderr_type: Option<String>,
+        ///What happens once a line of input violates its type
+        #[arg(long, value_name = "POLICY", default_value = "stop")]
+        stdin_on_fail: FailPolicy,
+        ///What happens once a line of stdout violates its type
+        #[arg(long, value_name = "POLICY", default_value = "stop")]
+        stdout_on_fail: FailPolicy,
+        ///What happens once a line of stderr violates its type
+        #[arg(long, value_name = "POLICY", default_value = "stop")]
+        stderr_on_fail: FailPolicy,
+        ///Signal to stop the command with on a violation, by name (TERM, SIGINT...) or number
+        #[arg(long, value_name = "SIGNAL", default_value = "TERM", value_parser = parse_signal)]
+        signal: Signal,
+        ///Seconds the command gets to exit after the signal before it's sent SIGKILL
+        #[arg(long, value_name = "SECS", default_value = "5", value_parser = parse_grace)]
+        grace: Duration,
+        ///Run the command in a process group of its own and signal the whole group, so everything it started is
+        ///stopped too (the command then no longer gets the terminal's Ctrl-C)
+        #[arg(long)]
+        process_group: bool,
+        ///Byte that records are delimited by, in the same form as the monitor's --delimiter
+        #[arg(long, value_name = "BYTE", value_parser = parse_delimiter, default_value = "\\n")]
+        delimiter: u8,
+        ///How a \r directly before each delimiter is handled, as for the monitor's --crlf
+        #[arg(long, value_name = "MODE", default_value = "strip")]
+        crlf: Crlf,
+        ///Command to run and its arguments, after --
+        #[arg(last = true, required = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+    ///Manage the cache of serialized DFAs that compile and dfa write to when not given -o. It lives in
+    ///$STREAMONITOR_CACHE_DIR if set, otherwise in streamonitor under $XDG_CACHE_HOME (or ~/.cache).
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    ///List the cached DFAs, least recently written first, with their size, age and what they were built from
+    List,
+    ///Remove cached DFAs by age, then the least recently written until the cache is small enough (--max-size 0 empties
+    ///it)
+    Gc {
+        ///Remove DFAs last written longer ago than this: a number followed by s, m, h, d or w (eg. 30d)
+        #[arg(long, value_name = "AGE", value_parser = parse_age, required_unless_present = "max_size")]
+        older_than: Option<Duration>,
+        ///Remove DFAs until the cache takes up at most this many bytes, optionally followed by K, M or G (eg. 100M)
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        max_size: Option<u64>,
+    },
+    ///Check that every cached DFA matches the hash it's named after and is valid, listing those that don't
+    Verify {
+        ///Remove the cached DFAs that fail verification
+        #[arg(long)]
+        remove: bool,
+    },
+    ///Print the path of the cache directory
+    Path,
+}
+
+/// --crlf's modes - the library's CrlfMode, or keep for no CRLF handling at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Crlf {
+    Strip,
+    Require,
+    Keep,
+}
+impl Crlf {
+    fn mode(self) -> Option<CrlfMode> {
+        match self { Crlf::Strip => Some(CrlfMode::Strip), Crlf::Require => Some(CrlfMode::Require), Crlf::Keep => None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Relation {
+    Subset,
+    Equivalent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DfaOperation {
+    Intersect,
+    Union,
+    Difference,
+    Complement,
+}
+
 fn main() {
     let args = Args::parse();
+    if let Some(command) = args.command {
+        match run_command(command) {
+            Ok(code) => exit(code),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(e.exit_code())
+            }
+        }
+    }
+    let typ = if args.line_dfa.is_some() {"Line DFA"}
+        else if !args.field.is_empty() || args.rest.is_some() {"Fields"}
+        else if args.header.is_some() || args.body.is_some() || args.footer.is_some() {"Sections"}
+        else if args.dfa_path.is_some() {"DFA"} else {"Regex"};
+    let trap = args.trap;
+    let rendering = if args.lossy { LineRendering::Lossy } else { LineRendering::Escaped };
+    let framing = Framing { 
+        delimiter: if args.null_data { b'\0' } else { args.delimiter.unwrap_or(b'\n') }, 
+        crlf: args.crlf.mode()
+    };
+    if let Err(e) = run(args, typ, rendering, framing) {
+        match e {
+            MonitorError::Validation(_) if trap => {}, //The trap is responsible for reporting validation failures
+            MonitorError::Validation(_) => eprintln!("{}\nType: {}", e.display(rendering), typ),
+            _ => eprintln!("{}", e),
+        }
+        exit(e.exit_code())
+    }
+}
+
+/// Sets up the input stream and DFA described by the arguments and validates the stream over the DFA
+fn run(args: Args, typ: &str, rendering: LineRendering, framing: Framing) -> Result<(), MonitorError> {
     //Parse input stream object and DFA from arguments
     let input_stream: Box<dyn BufRead> = match args.input_file {
         Some(path) => { //Input file provided
-            let input_file = File::open(path).expect("DFA path invalid");
+            let input_file = File::open(&path)
+                .map_err(|e| io::Error::new(e.kind(), format!("couldn't open input file {:?}: {}", path, e)))?;
             Box::new(BufReader::new(input_file))
         },
         None => { //No input file provided, default to stdin
             if atty::is(Stream::Stdin) { //No input stream provided - is attached to interactive terminal
-                panic!("No input stream piped in or provided via file") 
+                return Err(MonitorError::Usage(String::from("No input stream piped in or provided via file")))
             } else { //Input stream being piped in - return that stream
                 Box::new(io::stdin().lock())
             }
         }
     };
-    let typ = if let Some(_) = &args.dfa_path {"DFA"} else {"Regex"};
-    let dfa: Box<dyn Automaton> = match (args.dfa_path, args.regex, args.no_validation) {
-        (Some(path), None, false) => {
-            let dfa = Dfa::deserialize(path);
-            Box::new(dfa)
+    //Validate the stream and handle validation failure behavior
+    let res = if let Some(path) = args.line_dfa { //Stream typed line by line
+        let line_dfa = LineDfa::deserialize_from_json(path)?;
+        validate_line_classes(input_stream, &line_dfa, framing, args.report_all)
+    } else if !args.field.is_empty() || args.rest.is_some() { //Stream typed column by column
+        let columns = args.field.iter().map(|spec| load_type(spec)).collect::<Result<Vec<_>, _>>()?;
+        let count = args.columns.unwrap_or(LineCount { min: columns.len(), max: Some(columns.len()) });
+        let rest = args.rest.map(|spec| load_type(&spec)).transpose()?;
+        validate_fields(input_stream, Fields { columns, rest, separator: args.field_separator, count }, framing, args.report_all)
+    } else if args.header.is_some() || args.body.is_some() || args.footer.is_some() { //Multi-section stream type
+        let body = match (args.body, args.dfa_path, args.regex) {
+            (Some(spec), _, _) => Some(load_type(&spec)?),
+            (None, Some(path), _) => Some(load_dfa(path, args.mmap)?),
+            (None, None, Some(regex)) => Some(load_regex(regex)?),
+            (None, None, None) => None,
+        };
+        let mut sections = Vec::new();
+        if let Some(spec) = args.header { sections.push(Section { name: "header", dfa: load_type(&spec)?, lines: args.header_lines }); }
+        if let Some(dfa) = body { sections.push(Section { name: "body", dfa, lines: args.body_lines }); }
+        if let Some(spec) = args.footer { sections.push(Section { name: "footer", dfa: load_type(&spec)?, lines: args.footer_lines }); }
+        validate_sections(input_stream, sections, framing, args.report_all)
+    } else {
+        let dfa: Box<dyn Automaton> = match (args.dfa_path, args.regex, args.no_validation) {
+            (Some(path), None, false) => load_dfa(path, args.mmap)?,
+            (None, Some(regex), false) => load_regex(regex)?,
+            (None, None, true) => {
+                Box::new(DFA::new(r".*").unwrap())
+            },
+            (_, _, _) => {
+                return Err(MonitorError::Usage(String::from("No DFA or regular expression specified or multiple validation modes specified. Must either specify a DFA (via -d), regex (via -r), or set --no-validation.")))
+            }
+        };
+        feed_stream(input_stream, dfa.as_ref(), framing, args.report_all)
+    };
+    if let Err(e @ MonitorError::Validation(_)) = &res && args.trap {
+        let msg = format!("{}\nType: {}", e.display(rendering), typ);
+        kill_shell(msg.as_str()).map_err(|e| MonitorError::TrapSetup(e.to_string()))?;
+    }
+    res
+}
+
+/// Runs one of the monitor's subcommands, returning the code to exit with
+fn run_command(command: Command) -> Result<i32, MonitorError> {
+    match command {
+        Command::Compile { regex, output, json, flat } => {
+            let dfa = Dfa::from_regex(&regex)?;
+            write_dfa(dfa, output, json, flat, format!("This corresponds to the regular expression '{}'", regex), &regex)?;
+            Ok(0)
         },
-        (None, Some(regex), false) => {
-            Box::new(DFA::new(format!("^(?:{})$",regex).as_str()).expect("Input regular expression invalid"))
+        Command::Dfa { operation, dfa, other, output, json, flat } => {
+            let comment = format!("{:?} of {}{}", operation, dfa, other.as_ref().map(|other| format!(" and {}", other)).unwrap_or_default());
+            let dfa = load_operand(&dfa)?;
+            let result = match (operation, other) {
+                (DfaOperation::Complement, None) => dfa.complement(),
+                (DfaOperation::Complement, Some(_)) => return Err(MonitorError::Usage(String::from("complement takes a single DFA"))),
+                (_, None) => return Err(MonitorError::Usage(format!("{} takes two DFAs", operation.to_possible_value().expect("No skipped variants").get_name()))),
+                (DfaOperation::Intersect, Some(other)) => dfa.intersect(&load_operand(&other)?),
+                (DfaOperation::Union, Some(other)) => dfa.union(&load_operand(&other)?),
+                (DfaOperation::Difference, Some(other)) => dfa.difference(&load_operand(&other)?),
+            };
+            write_dfa(result, output, json, flat, comment.clone(), &comment)?;
+            Ok(0)
         },
-        (None, None, true) => {
-            Box::new(DFA::new(r".*").unwrap())
+        Command::Check { relation, dfa: dfa_spec, other: other_spec } => {
+            let (dfa, other) = (load_operand(&dfa_spec)?, load_operand(&other_spec)?);
+            let verdict = match relation {
+                Relation::Subset => dfa.is_subset_of(&other),
+                Relation::Equivalent => dfa.equivalent(&other),
+            };
+            let relation = match relation { Relation::Subset => "a subset of", Relation::Equivalent => "equivalent to" };
+            match verdict {
+                Ok(()) => { println!("{} is {} {}", dfa_spec, relation, other_spec); Ok(0) },
+                Err(line) => {
+                    let only = if dfa.matches(&line) { &dfa_spec } else { &other_spec };
+                    println!("{} is not {} {}\nCounterexample (matched by {} only): {:?}", dfa_spec, relation, other_spec, only, LineRendering::Escaped.render(&line));
+                    Ok(MonitorError::VALIDATION_EXIT_CODE)
+                },
+            }
         },
-        (_, _, _) => {
-            eprintln!("No DFA or regular expression specified or multiple validation modes specified. Must either specify a DFA (via -d), regex (via -r), or set --no-validation.");
-            exit(1)
-        }
+        Command::Sample { dfa, count, max_len, seed, by_length, near_misses, enumerate, delimiter } => {
+            let dfa = load_operand(&dfa)?;
+            let seed = seed.unwrap_or_else(|| std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64));
+            let weighting = if by_length { Weighting::ByLength } else { Weighting::Uniform };
+            //Lines containing the delimiter would read back as several lines
+            let containing_delimiter = Dfa::from_regex(&format!("@\\{}@", delimiter as char))?;
+            let lines: Box<dyn Iterator<Item = Vec<u8>>> = if near_misses {
+                Box::new(dfa.near_misses(seed, max_len, weighting).filter(|line| !line.contains(&delimiter)))
+            } else {
+                let dfa = dfa.difference(&containing_delimiter);
+                if enumerate { Box::new(dfa.enumerate(max_len)) } else { Box::new(dfa.sample(seed, max_len, weighting)) }
+            };
+            let mut stdout = io::stdout().lock();
+            for mut line in lines.take(count) {
+                line.push(delimiter);
+                stdout.write_all(&line)?;
+            }
+            stdout.flush()?;
+            Ok(0)
+        },
+        Command::Regex { dfa } => {
+            println!("{}", load_operand(&dfa)?.to_regex());
+            Ok(0)
+        },
+        Command::Graph { dfa, format, hide_dead, output } => {
+            let dfa = load_operand(&dfa)?;
+            let graph = match format {
+                GraphFormat::Dot => dfa.to_dot(hide_dead),
+                GraphFormat::Mermaid => dfa.to_mermaid(hide_dead),
+            };
+            match output {
+                Some(path) => fs::write(path, graph)?,
+                None => print!("{}", graph),
+            }
+            Ok(0)
+        },
+        Command::Run { stdin_type, stdout_type, stderr_type, stdin_on_fail, stdout_on_fail, stderr_on_fail, signal, grace, process_group, delimiter, crlf, command } => {
+            let typed = |spec: Option<String>, on_fail| spec.map(|spec| load_type(&spec).map(|dfa| StreamType { dfa, spec, on_fail })).transpose();
+            let types = StreamTypes { stdin: typed(stdin_type, stdin_on_fail)?, stdout: typed(stdout_type, stdout_on_fail)?, stderr: typed(stderr_type, stderr_on_fail)? };
+            run_wrapped(&command, types, Framing { delimiter, crlf: crlf.mode() }, Stop { signal, grace, process_group })
+        },
+        Command::Cache { action } => run_cache_action(action, &Cache::locate()),
+    }
+}
+
+/// Runs one of the cache subcommand's actions, returning the code to exit with
+fn run_cache_action(action: CacheAction, cache: &Cache) -> Result<i32, MonitorError> {
+    let name = |path: &PathBuf| path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+    match action {
+        CacheAction::List => {
+            let now = SystemTime::now();
+            for entry in cache.entries()? {
+                let age = now.duration_since(entry.modified).unwrap_or_default();
+                println!("{}  {:>9}  {:>4} ago  {}", name(&entry.path), human_size(entry.size), human_age(age), entry.source.as_deref().unwrap_or("-"));
+            }
+            Ok(0)
+        },
+        CacheAction::Gc { older_than, max_size } => {
+            let removed = cache.gc(older_than, max_size)?;
+            for entry in &removed { println!("Removed {}", name(&entry.path)); }
+            println!("Removed {} DFAs, freeing {}", removed.len(), human_size(removed.iter().map(|entry| entry.size).sum()));
+            Ok(0)
+        },
+        CacheAction::Verify { remove } => {
+            let mut failed = 0;
+            for entry in cache.entries()? {
+                match entry.verify() {
+                    Ok(()) => println!("{}: ok", name(&entry.path)),
+                    Err(reason) => {
+                        failed += 1;
+                        println!("{}: {}", name(&entry.path), reason);
+                        if remove { entry.remove()?; }
+                    },
+                }
+            }
+            Ok(if failed == 0 { 0 } else { MonitorError::VALIDATION_EXIT_CODE })
+        },
+        CacheAction::Path => {
+            println!("{}", cache.dir().display());
+            Ok(0)
+        },
+    }
+}
+
+/// Parses an age given to the cache subcommand as a number followed by s, m, h, d or w
+fn parse_age(s: &str) -> Result<Duration, String> {
+    let unit = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        _ => return Err(String::from("expected a number followed by s, m, h, d or w (eg. 30d)")),
     };
-    //Validate the stream and handle validation failure behavior
-    if let Err(e) = validate_stream(input_stream, dfa) {
-        let msg = match e {
-            ValidationFailure::Partial(line) => format!("Validation failed (partial match)\nIncriminating line: {}\nType: {}", line, typ),
-            ValidationFailure::Whole(line) => format!("Validation failed\nIncriminating line: {}\nType: {}", line, typ),
-        };
-        if args.trap { kill_shell(msg.as_str()).expect("Trap not properly set up (and validation failed)") }
-        else { panic!("{}", msg) }
+    let count: u64 = s[..s.len() - 1].parse().map_err(|_| format!("invalid number in age {:?}", s))?;
+    Ok(Duration::from_secs(count.saturating_mul(unit)))
+}
+
+/// Parses a size given to the cache subcommand as a number of bytes, optionally followed by K, M or G (powers of 1024)
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, unit) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    let count: u64 = digits.parse().map_err(|_| format!("expected a number of bytes, optionally followed by K, M or G (eg. 100M), not {:?}", s))?;
+    Ok(count.saturating_mul(unit))
+}
+
+fn human_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1048576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0),
     }
-    
-}
-
-#[derive(Debug)]
-enum ValidationFailure {
-    Partial(String),
-    Whole(String),
-}
-
-/// Given a stream and a DFA, walks the DFA over the stream, writing each line of the stream to stdout as it
-/// validates
-fn validate_stream(stream: Box<dyn BufRead>, dfa: Box<dyn Automaton>) -> Result<(), ValidationFailure> {
-    let mut stream_empty = true; //Annoying boolean flag to deal with empty stream edge case - annoying, but I see no better option
-    for line in stream.lines() {
-        if stream_empty { stream_empty = false; }
-        let line = line.expect("Error grabbing next line");
-        //let _state = dfa.start_state(&Config::new()).expect("Couldn't bring DFA to start state");
-        match dfa.try_search_fwd(&Input::new(&line.as_bytes())).expect("DFA search errored") { //try_search_fwd() is not guaranteed to find the longest match unless regex is anchored!!!
-            Some(mtch) => {
-                if mtch == HalfMatch::must(0, line.len()) { println!("{}", line.as_str()) } //Write line to stdout - done line by line to preserve streaming
-                else { return Err(ValidationFailure::Partial(line)) }
-            },
-            None => return Err(ValidationFailure::Whole(line))
+}
+
+fn human_age(age: Duration) -> String {
+    match age.as_secs() {
+        secs @ 0..60 => format!("{}s", secs),
+        secs @ 60..3600 => format!("{}m", secs / 60),
+        secs @ 3600..86400 => format!("{}h", secs / 3600),
+        secs => format!("{}d", secs / 86400),
+    }
+}
+
+/// Writes a DFA produced by a subcommand out (to the DFA cache by default, noting its source there), printing where the
+/// serialized DFA went
+fn write_dfa(dfa: Dfa, output: Option<PathBuf>, json: Option<PathBuf>, flat: bool, comment: String, source: &str) -> Result<(), MonitorError> {
+    if let Some(json_path) = json { dfa.serialize_to_json(&json_path, Some(comment))?; }
+    let path = match output {
+        Some(path) if flat => { dfa.serialize_flat_to(&path)?; path },
+        Some(path) => { dfa.serialize_to(&path)?; path },
+        None => dfa.serialize_into(&Cache::locate(), Some(source))?,
+    };
+    println!("{}", path.display());
+    Ok(())
+}
+
+/// Loads a DFA given to a subcommand as dfa:PATH, json:PATH or a bare path (JSON if it ends in .json)
+fn load_operand(spec: &str) -> Result<Dfa, MonitorError> {
+    match spec.split_once(':') {
+        Some(("dfa", path)) => Dfa::deserialize(PathBuf::from(path)),
+        Some(("json", path)) => Dfa::deserialize_from_json(PathBuf::from(path)),
+        _ if spec.ends_with(".json") => Dfa::deserialize_from_json(PathBuf::from(spec)),
+        _ => Dfa::deserialize(PathBuf::from(spec)),
+    }
+}
+
+/// Loads a serialized DFA, memory mapping it if it's flat and mmap is set
+fn load_dfa(path: PathBuf, mmap: bool) -> Result<Box<dyn Automaton + Send + Sync>, MonitorError> {
+    //SAFETY: --mmap is only for files nothing changes while the monitor runs, as its help says
+    let dfa = if mmap { unsafe { Dfa::deserialize_mapped(path)? } } else { Dfa::deserialize(path)? };
+    Ok(Box::new(dfa))
+}
+
+/// Compiles a regex that whole lines must match
+fn load_regex(regex: String) -> Result<Box<dyn Automaton + Send + Sync>, MonitorError> {
+    let dfa = regex_dfa(format!("^(?:{})$",regex).as_str())
+        .map_err(|reason| MonitorError::RegexCompile { regex, reason })?;
+    Ok(Box::new(dfa))
+}
+
+/// Loads the DFA for a type given on the command line as dfa:PATH, json:PATH or regex:PATTERN (bare values are
+/// treated as regexes)
+fn load_type(spec: &str) -> Result<Box<dyn Automaton + Send + Sync>, MonitorError> {
+    match spec.split_once(':') {
+        Some(("dfa", path)) => load_dfa(PathBuf::from(path), false),
+        Some(("json", path)) => Ok(Box::new(Dfa::deserialize_from_json(PathBuf::from(path))?)),
+        Some(("regex", regex)) => load_regex(regex.to_string()),
+        _ => load_regex(spec.to_string()),
+    }
+}
+
+/// Compiles a regex into a DFA over raw bytes - with Unicode mode disabled (eg. via (?-u)), patterns may match
+/// bytes that aren't valid UTF-8. Errors are rendered with their full source chain.
+fn regex_dfa(regex: &str) -> Result<DFA<Vec<u32>>, String> {
+    DFA::builder()
+        .syntax(syntax::Config::new().utf8(false))
+        .thompson(thompson::Config::new().utf8(false))
+        .build(regex)
+        .map_err(|e| error_chain(&e))
+}
+
+/// Renders an error alongside every error in its source chain (regex_automata's top level errors are rather terse)
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut msg = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        msg += format!(": {}", e).as_str();
+        source = e.source();
+    }
+    msg
+}
+
+/// Parses a record delimiter given on the command line into its byte
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    let hex = s.strip_prefix("0x").or_else(|| s.strip_prefix("\\x"));
+    match (s, hex) {
+        (_, Some(hex)) => u8::from_str_radix(hex, 16).map_err(|_| format!("{:?} is not a hex byte", s)),
+        ("\\t", _) => Ok(b'\t'),
+        ("\\n", _) => Ok(b'\n'),
+        ("\\r", _) => Ok(b'\r'),
+        ("\\0", _) => Ok(b'\0'),
+        _ if s.len() == 1 => Ok(s.as_bytes()[0]),
+        _ => Err(format!("{:?} is not a single byte - use an ASCII character, an escape or 0xNN", s)),
+    }
+}
+
+/// Pushes the stream through the validator as it arrives, handing each verdict to on_verdict as soon as it's reached
+/// - in between lines, so it can select the automata the next line is walked over
+fn feed_lines<A: Automaton>(
+    stream: &mut dyn BufRead, validator: &mut StreamValidator<A>, mut on_verdict: impl FnMut(&mut StreamValidator<A>, LineVerdict) -> Result<(), MonitorError>,
+) -> Result<(), MonitorError> {
+    loop {
+        let buf = stream.fill_buf()?;
+        if buf.is_empty() {
+            for verdict in validator.finish()? { on_verdict(validator, verdict)?; }
+            return Ok(())
         }
+        let (used, verdict) = validator.feed_line(buf)?;
+        stream.consume(used);
+        if let Some(verdict) = verdict { on_verdict(validator, verdict)?; }
     }
-    //If the stream is empty and the DFA doesn't accept "", it needs to error
-    if stream_empty && !dfa.has_empty() { return Err(ValidationFailure::Whole(String::new())) }
+}
+
+/// Writes a validated line to stdout, byte for byte as it was read (putting back a \r the framing took off), followed
+/// by the delimiter
+fn emit_line(stdout: &mut impl Write, line: &[u8], cr: bool, framing: Framing) -> io::Result<()> {
+    stdout.write_all(line)?;
+    if cr { stdout.write_all(b"\r")?; }
+    stdout.write_all(&[framing.delimiter])?;
+    //Stdout is line buffered, so newline delimited lines get flushed on their own - done line by line to preserve streaming
+    if framing.delimiter != b'\n' { stdout.flush()?; }
     Ok(())
 }
 
+/// Given a stream and a DFA, validates the stream over the DFA as it arrives, writing each line to stdout as it
+/// validates and stopping at the first failing line - or with report_all set, keeping going past failing lines (which
+/// are withheld from stdout) and returning every failure alongside its 1-based line number once the stream is exhausted
+fn feed_stream(mut stream: Box<dyn BufRead>, dfa: &dyn Automaton, framing: Framing, report_all: bool) -> Result<(), MonitorError> {
+    let mut stdout = io::stdout().lock();
+    let mut validator = StreamValidator::with_framing(dfa, framing)?;
+    let mut failures = Vec::new();
+    loop {
+        let buf = stream.fill_buf()?;
+        let verdicts = if buf.is_empty() { validator.finish()? } else { validator.feed(buf)? };
+        let len = buf.len();
+        stream.consume(len);
+        for verdict in verdicts {
+            match verdict {
+                LineVerdict::Valid(line, _, cr) => emit_line(&mut stdout, &line, cr, framing)?,
+                LineVerdict::Invalid(line_no, failure) => {
+                    failures.push((line_no, failure));
+                    if !report_all { return Err(MonitorError::Validation(failures)) }
+                },
+            }
+        }
+        if len == 0 { break }
+    }
+    if failures.is_empty() { Ok(()) } else { Err(MonitorError::Validation(failures)) }
+}
+
 /// Assuming the appropriate environment variables and trap are set, sends a message to be print 
 /// and a kill signal to the parent shell process
 fn kill_shell(message: &str) -> Result<(), Box<dyn std::error::Error>> {
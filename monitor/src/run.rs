@@ -0,0 +1,208 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use clap::ValueEnum;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use regex_automata::dfa::Automaton;
+use monitor::{Framing, LineRendering, LineVerdict, MonitorError, StreamValidator, ValidationFailure};
+use crate::emit_line;
+
+/// Bytes read from a stream at a time
+const CHUNK_LEN: usize = 8 * 1024;
+/// How often the command is checked on while waiting for it
+const POLL: Duration = Duration::from_millis(10);
+
+/// How a wrapped command gets stopped once one of its streams violates its type
+#[derive(Debug, Clone, Copy)]
+pub struct Stop {
+    pub signal: Signal,
+    pub grace: Duration, //How long the command gets to exit after the signal before it's sent SIGKILL
+    pub process_group: bool, //Whether the command runs in a process group of its own, which is signalled as a whole
+}
+
+/// What happens to a stream of a wrapped command once one of its lines violates the stream's type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FailPolicy {
+    Stop, //Stop the command
+    Drop, //Withhold the line and carry on with the next
+    Close, //Withhold the rest of the stream (for stdin, the command gets to the end of its input)
+}
+
+/// The type a standard stream of a wrapped command is validated against
+pub struct StreamType {
+    pub spec: String, //The type as given on the command line
+    pub dfa: Box<dyn Automaton + Send + Sync>,
+    pub on_fail: FailPolicy,
+}
+
+/// Types for the standard streams of a wrapped command - streams without one are passed straight through
+#[derive(Default)]
+pub struct StreamTypes {
+    pub stdin: Option<StreamType>,
+    pub stdout: Option<StreamType>,
+    pub stderr: Option<StreamType>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pipe { Stdin, Stdout, Stderr }
+impl fmt::Display for Pipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self { Pipe::Stdin => "stdin", Pipe::Stdout => "stdout", Pipe::Stderr => "stderr" })
+    }
+}
+
+/// What the threads interposing on the command's streams tell the thread watching over it
+#[derive(Debug)]
+pub enum Event {
+    Violation(Pipe, usize, ValidationFailure),
+    Closed(Pipe, Result<(), MonitorError>),
+}
+
+/// Runs the command with each of its typed standard streams interposed on: input is validated before it's passed
+/// on to the command and output before it's emitted, a line at a time. A line violating its stream's type is handled
+/// as the stream's policy says. Returns the code to exit with: the command's own if every stream validated
+/// (reporting if it failed regardless), or VALIDATION_EXIT_CODE if one didn't.
+pub fn run_wrapped(command: &[String], types: StreamTypes, framing: Framing, stop: Stop) -> Result<i32, MonitorError> {
+    let (program, args) = command.split_first().ok_or_else(|| MonitorError::Usage(String::from("No command given to run")))?;
+    let piped = |typ: &Option<StreamType>| if typ.is_some() { Stdio::piped() } else { Stdio::inherit() };
+    let mut child = Command::new(program);
+    child.args(args).stdin(piped(&types.stdin)).stdout(piped(&types.stdout)).stderr(piped(&types.stderr));
+    if stop.process_group { child.process_group(0); }
+    let mut child = child.spawn().map_err(|e| io::Error::new(e.kind(), format!("couldn't run {:?}: {}", program, e)))?;
+    let typed: Vec<(Pipe, String, FailPolicy)> = [(Pipe::Stdin, &types.stdin), (Pipe::Stdout, &types.stdout), (Pipe::Stderr, &types.stderr)]
+        .into_iter().filter_map(|(pipe, typ)| typ.as_ref().map(|typ| (pipe, typ.spec.clone(), typ.on_fail))).collect();
+    let (events, received) = mpsc::channel();
+    //The stdin thread is left to itself: it may be blocked reading input that never comes once the command is done
+    if let Some(typ) = types.stdin {
+        let sink = child.stdin.take().expect("Child's stdin was piped");
+        spawn_interposer(Pipe::Stdin, io::stdin(), sink, typ, framing, events.clone());
+    }
+    let mut open = 0; //Output streams still being read
+    if let Some(typ) = types.stdout {
+        spawn_interposer(Pipe::Stdout, child.stdout.take().expect("Child's stdout was piped"), io::stdout(), typ, framing, events.clone());
+        open += 1;
+    }
+    if let Some(typ) = types.stderr {
+        spawn_interposer(Pipe::Stderr, child.stderr.take().expect("Child's stderr was piped"), io::stderr(), typ, framing, events.clone());
+        open += 1;
+    }
+    drop(events);
+    let mut status = None;
+    let mut stopped = None; //The stream whose violation stopped the command
+    let mut violated = Vec::new();
+    //Output is read to its end, unless the command was stopped (whatever it started may be holding its pipes open)
+    while status.is_none() || (open > 0 && stopped.is_none()) {
+        match received.recv_timeout(POLL) {
+            Ok(Event::Violation(pipe, line_no, failure)) => {
+                let (_, spec, on_fail) = typed.iter().find(|(typed, _, _)| *typed == pipe).expect("Only typed streams are interposed on");
+                eprintln!("{}\nType ({}): {}", MonitorError::Validation(vec![(line_no, failure)]).display(LineRendering::Escaped), pipe, spec);
+                if !violated.contains(&pipe) { violated.push(pipe); }
+                if *on_fail == FailPolicy::Stop && stopped.is_none() {
+                    if status.is_none() { status = Some(stop_child(&mut child, stop)?); }
+                    stopped = Some(pipe);
+                }
+            },
+            Ok(Event::Closed(pipe, result)) => {
+                if pipe != Pipe::Stdin { open -= 1; }
+                if let Err(e) = result {
+                    if status.is_none() { stop_child(&mut child, stop)?; }
+                    return Err(e)
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => {},
+            //Every interposer is done, so all that's left is the command
+            Err(RecvTimeoutError::Disconnected) => if status.is_none() { status = Some(child.wait()?); },
+        }
+        if status.is_none() { status = child.try_wait()?; }
+    }
+    let status = status.expect("Loop runs until the command is done");
+    let violated: Vec<String> = violated.iter().map(|pipe| pipe.to_string()).collect();
+    if let Some(pipe) = stopped {
+        eprintln!("Type violated: stopped {:?} on its {} with {} ({})", program, pipe, stop.signal, status);
+    } else if !violated.is_empty() {
+        eprintln!("Type violated: withheld lines of {:?}'s {} failing their type ({})", program, violated.join(" and "), status);
+    } else {
+        if !status.success() { eprintln!("Command failed: {:?} exited with {} (its streams matched their types)", program, status); }
+        return Ok(exit_code(status))
+    }
+    Ok(MonitorError::VALIDATION_EXIT_CODE)
+}
+
+fn spawn_interposer<R: Read + Send + 'static, W: Write + Send + 'static>(pipe: Pipe, source: R, sink: W, typ: StreamType, framing: Framing, events: Sender<Event>) {
+    thread::spawn(move || {
+        let result = interpose(pipe, source, sink, &typ, framing, &events);
+        let _ = events.send(Event::Closed(pipe, result));
+    });
+}
+
+/// Passes a stream on from source to sink a line at a time as the lines validate, reporting each line that doesn't
+/// and handling it as the type's policy says. A sink that's been closed (say, a command no longer reading its input)
+/// ends the stream early.
+pub fn interpose(pipe: Pipe, mut source: impl Read, sink: impl Write, typ: &StreamType, framing: Framing, events: &Sender<Event>) -> Result<(), MonitorError> {
+    let mut validator = StreamValidator::with_framing(typ.dfa.as_ref(), framing)?;
+    let mut sink = Some(sink);
+    let mut chunk = vec![0; CHUNK_LEN];
+    loop {
+        let read = match source.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let verdicts = if read == 0 { validator.finish()? } else { validator.feed(&chunk[..read])? };
+        for verdict in verdicts {
+            match verdict {
+                LineVerdict::Valid(line, _, cr) => if let Some(out) = &mut sink {
+                    match emit_line(out, &line, cr, framing).and_then(|_| out.flush()) {
+                        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                        result => result?,
+                    }
+                },
+                LineVerdict::Invalid(line_no, failure) => {
+                    let _ = events.send(Event::Violation(pipe, line_no, failure));
+                    match typ.on_fail {
+                        FailPolicy::Stop => return Ok(()),
+                        FailPolicy::Drop => {},
+                        FailPolicy::Close => sink = None,
+                    }
+                },
+            }
+        }
+        if read == 0 { return Ok(()) }
+    }
+}
+
+/// Signals the command to stop, following up with SIGKILL if it hasn't exited by the end of the grace period
+fn stop_child(child: &mut Child, stop: Stop) -> io::Result<ExitStatus> {
+    let pid = Pid::from_raw(child.id() as i32);
+    //Either may fail if the command has already exited, which is just as good
+    let send = |sig: Signal| { let _ = if stop.process_group { signal::killpg(pid, sig) } else { signal::kill(pid, sig) }; };
+    send(stop.signal);
+    let deadline = Instant::now() + stop.grace;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? { return Ok(status) }
+        thread::sleep(POLL);
+    }
+    send(Signal::SIGKILL);
+    child.wait()
+}
+
+/// Exit code passing a command's exit status on, shell style (128 + the signal for commands killed by one)
+fn exit_code(status: ExitStatus) -> i32 { status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0)) }
+
+/// Parses a signal given on the command line by name (with or without SIG, in any case) or number
+pub fn parse_signal(s: &str) -> Result<Signal, String> {
+    if let Ok(number) = s.parse::<i32>() { return Signal::try_from(number).map_err(|_| format!("{} is not a signal number", number)) }
+    let name = s.to_ascii_uppercase();
+    let name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+    name.parse().map_err(|_| format!("{:?} is not a signal name (eg. TERM, INT or KILL)", s))
+}
+
+/// Parses a grace period given on the command line in (possibly fractional) seconds
+pub fn parse_grace(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok()).ok_or_else(|| format!("{:?} is not a number of seconds", s))
+}
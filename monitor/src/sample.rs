@@ -0,0 +1,181 @@
+use crate::{byte_dfa::ByteDfa, Dfa};
+
+/// How `Dfa::sample` weighs the lengths of the lines it generates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weighting {
+    /// Every accepted line within the length bound is equally likely (so the longest lines dominate)
+    Uniform,
+    /// Every length with accepted lines is equally likely, then every line of the chosen length
+    ByLength,
+}
+
+/// Consecutive failed edits after which `NearMisses` gives up
+const NEAR_MISS_ATTEMPTS: usize = 1000;
+
+/// Accepted lines in shortlex order (shortest first, then in byte order), up to a length bound
+pub struct Enumeration {
+    dfa: ByteDfa,
+    finishes: Vec<Vec<bool>>, //finishes[k][state]: whether some line of exactly k bytes is accepted from state
+    len: usize,
+    next_len: usize,
+    stack: Vec<(usize, usize)>, //State and next byte to try at each depth
+    line: Vec<u8>,
+}
+impl Enumeration {
+    pub(crate) fn new(dfa: &Dfa, max_len: usize) -> Self {
+        let dfa = ByteDfa::from_dfa(dfa);
+        let finishes = counts(&dfa, max_len).0.into_iter().map(|level| level.into_iter().map(|count| count > 0.0).collect()).collect();
+        Enumeration { dfa, finishes, len: 0, next_len: 0, stack: Vec::new(), line: Vec::new() }
+    }
+    fn backtrack(&mut self) {
+        self.stack.pop();
+        self.line.pop();
+    }
+}
+impl Iterator for Enumeration {
+    type Item = Vec<u8>;
+    fn next(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let Some(&(state, from)) = self.stack.last() else {
+                //Move on to the next length
+                if self.next_len >= self.finishes.len() { return None }
+                self.len = self.next_len;
+                self.next_len += 1;
+                if self.finishes[self.len][self.dfa.start] { self.stack.push((self.dfa.start, 0)); }
+                continue
+            };
+            let remaining = self.len - self.line.len();
+            if remaining == 0 {
+                let line = self.line.clone();
+                self.backtrack();
+                return Some(line)
+            }
+            //Only step into states that can still finish a line of the current length
+            match (from..256).find(|byte| self.finishes[remaining - 1][self.dfa.next[state][*byte]]) {
+                Some(byte) => {
+                    self.stack.last_mut().unwrap().1 = byte + 1;
+                    self.stack.push((self.dfa.next[state][byte], 0));
+                    self.line.push(byte as u8);
+                },
+                None => self.backtrack(),
+            }
+        }
+    }
+}
+
+/// Endless random accepted lines up to a length bound - ends straight away if there are none
+pub struct Samples {
+    dfa: ByteDfa,
+    levels: Vec<Vec<f64>>, //levels[k][state]: accepted lines of exactly k bytes from state, scaled per level
+    length_weights: Vec<f64>,
+    rng: SplitMix64,
+}
+impl Samples {
+    pub(crate) fn new(dfa: &Dfa, seed: u64, max_len: usize, weighting: Weighting) -> Self {
+        let dfa = ByteDfa::from_dfa(dfa);
+        let (levels, log_scales) = counts(&dfa, max_len);
+        let log_weights: Vec<f64> = levels.iter().zip(&log_scales).map(|(level, log_scale)| match (level[dfa.start], weighting) {
+            (0.0, _) => f64::NEG_INFINITY,
+            (count, Weighting::Uniform) => log_scale + count.ln(),
+            (_, Weighting::ByLength) => 0.0,
+        }).collect();
+        let max = log_weights.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let length_weights = log_weights.iter().map(|log_weight| if max.is_finite() { (log_weight - max).exp() } else { 0.0 }).collect();
+        Samples { dfa, levels, length_weights, rng: SplitMix64(seed) }
+    }
+}
+impl Iterator for Samples {
+    type Item = Vec<u8>;
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let len = self.rng.choose(&self.length_weights)?;
+        let mut line = Vec::with_capacity(len);
+        let mut state = self.dfa.start;
+        for remaining in (0..len).rev() {
+            let weights: Vec<f64> = self.dfa.next[state].iter().map(|next| self.levels[remaining][*next]).collect();
+            let byte = self.rng.choose(&weights).expect("Sampled a length with no accepted lines");
+            line.push(byte as u8);
+            state = self.dfa.next[state][byte];
+        }
+        Some(line)
+    }
+}
+
+/// Endless random rejected lines, each one byte inserted, deleted or substituted away from a sampled accepted line -
+/// ends once edits keep failing to produce one (eg. if the DFA accepts every line)
+pub struct NearMisses {
+    samples: Samples,
+    alphabet: Vec<u8>, //Bytes some live state has a transition on, which make for more realistic edits
+}
+impl NearMisses {
+    pub(crate) fn new(dfa: &Dfa, seed: u64, max_len: usize, weighting: Weighting) -> Self {
+        let samples = Samples::new(dfa, seed, max_len, weighting);
+        let alphabet = (0..=255u8).filter(|byte| samples.dfa.next.iter().skip(1).any(|row| row[*byte as usize] != 0)).collect();
+        NearMisses { samples, alphabet }
+    }
+    fn edit_byte(&mut self) -> u8 {
+        let rng = &mut self.samples.rng;
+        if self.alphabet.is_empty() || rng.below(2) == 0 { rng.below(256) as u8 } else { self.alphabet[rng.below(self.alphabet.len())] }
+    }
+}
+impl Iterator for NearMisses {
+    type Item = Vec<u8>;
+    fn next(&mut self) -> Option<Vec<u8>> {
+        for _ in 0..NEAR_MISS_ATTEMPTS {
+            let mut line = self.samples.next()?;
+            match self.samples.rng.below(3) {
+                0 => { let at = self.samples.rng.below(line.len() + 1); let byte = self.edit_byte(); line.insert(at, byte); },
+                1 if !line.is_empty() => { line.remove(self.samples.rng.below(line.len())); },
+                _ if !line.is_empty() => { let at = self.samples.rng.below(line.len()); line[at] = self.edit_byte(); },
+                _ => continue,
+            }
+            let state = line.iter().fold(self.samples.dfa.start, |state, byte| self.samples.dfa.next[state][*byte as usize]);
+            if !self.samples.dfa.accept[state] { return Some(line) }
+        }
+        None
+    }
+}
+
+/// Number of accepted lines of each length from each state, up to `max_len`. Counts grow exponentially, so each
+/// length's are scaled down to at most 1, alongside the natural log of the factor they were scaled down by.
+fn counts(dfa: &ByteDfa, max_len: usize) -> (Vec<Vec<f64>>, Vec<f64>) {
+    let mut levels = vec![dfa.accept.iter().map(|accept| if *accept { 1.0 } else { 0.0 }).collect::<Vec<f64>>()];
+    let mut log_scales = vec![0.0];
+    for k in 1..=max_len {
+        let mut level: Vec<f64> = dfa.next.iter().map(|row| row.iter().map(|next| levels[k - 1][*next]).sum()).collect();
+        let max = level.iter().copied().fold(0.0, f64::max);
+        let mut log_scale = log_scales[k - 1];
+        if max > 0.0 {
+            level.iter_mut().for_each(|count| *count /= max);
+            log_scale += max.ln();
+        }
+        levels.push(level);
+        log_scales.push(log_scale);
+    }
+    (levels, log_scales)
+}
+
+/// Small seeded generator, so samples stay reproducible from their seed
+struct SplitMix64(u64);
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+    fn unit(&mut self) -> f64 { (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64 }
+    fn below(&mut self, bound: usize) -> usize { (self.unit() * bound as f64) as usize }
+    /// Index picked with probability proportional to its weight, if any weight is positive
+    fn choose(&mut self, weights: &[f64]) -> Option<usize> {
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 { return None }
+        let mut target = self.unit() * total;
+        let last = weights.iter().rposition(|weight| *weight > 0.0)?;
+        for (index, weight) in weights.iter().enumerate().take(last) {
+            if target < *weight { return Some(index) }
+            target -= weight;
+        }
+        Some(last)
+    }
+}
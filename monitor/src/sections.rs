@@ -0,0 +1,121 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead};
+use regex_automata::dfa::Automaton;
+use monitor::{LineVerdict, MonitorError, StreamValidator, ValidationFailure};
+use crate::{emit_line, feed_lines, Framing};
+
+/// Bounds on how many lines a section of a stream may span
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineCount {
+    pub min: usize,
+    pub max: Option<usize>, //None for no upper bound
+}
+impl LineCount {
+    /// Parses a line count given on the command line - N, N.., ..M or N..M (bounds inclusive)
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let bound = |b: &str| b.trim().parse::<usize>().map_err(|_| format!("{:?} is not a valid line count", s));
+        let count = match s.split_once("..") {
+            None => { let n = bound(s)?; LineCount { min: n, max: Some(n) } },
+            Some((min, max)) => LineCount {
+                min: if min.trim().is_empty() { 0 } else { bound(min)? },
+                max: if max.trim().is_empty() { None } else { Some(bound(max)?) },
+            },
+        };
+        match count.max {
+            Some(max) if max < count.min => Err(format!("{:?} has a maximum below its minimum", s)),
+            _ => Ok(count),
+        }
+    }
+    fn allows_more(&self, count: usize) -> bool { self.max.is_none_or(|max| count < max) }
+    pub fn contains(&self, count: usize) -> bool { count >= self.min && self.max.is_none_or(|max| count <= max) }
+}
+
+/// One section of a stream whose lines must all match the section's DFA
+pub struct Section {
+    pub name: &'static str,
+    pub dfa: Box<dyn Automaton>,
+    pub lines: LineCount,
+}
+
+/// Tracks every place in the sequence of sections the stream could be at so far (a line may match several sections,
+/// eg. the last body line and the footer). Positions are (section index, lines read in that section), with the line
+/// count saturating once more lines can no longer make a difference.
+struct SectionWalk<'a> {
+    sections: &'a [Section],
+    positions: BTreeSet<(usize, usize)>,
+}
+impl<'a> SectionWalk<'a> {
+    fn new(sections: &'a [Section]) -> Self { SectionWalk { sections, positions: BTreeSet::from([(0, 0)]) } }
+    /// Sections that could be entered (or stayed in) from the position (section, count), passing over any optional
+    /// sections in between
+    fn next_sections(&self, section: usize, count: usize) -> Vec<usize> {
+        let mut next = Vec::new();
+        if section >= self.sections.len() { return next }
+        if self.sections[section].lines.allows_more(count) { next.push(section); }
+        if count < self.sections[section].lines.min { return next }
+        for (j, later) in self.sections.iter().enumerate().skip(section + 1) {
+            if later.lines.allows_more(0) { next.push(j); }
+            if later.lines.min > 0 { break }
+        }
+        next
+    }
+    /// Every section the next line could belong to, in order
+    fn candidates(&self) -> Vec<usize> {
+        let candidates: BTreeSet<usize> = self.positions.iter().flat_map(|(section, count)| self.next_sections(*section, *count)).collect();
+        candidates.into_iter().collect()
+    }
+    /// Moves past a line that was accepted by the given sections
+    fn advance(&mut self, accepted_by: &[usize]) {
+        let mut positions = BTreeSet::new();
+        for (section, count) in &self.positions {
+            for next in self.next_sections(*section, *count) {
+                if !accepted_by.contains(&next) { continue }
+                let count = if next == *section { count + 1 } else { 1 };
+                let lines = self.sections[next].lines;
+                positions.insert((next, if lines.max.is_none() { count.min(lines.min) } else { count }));
+            }
+        }
+        self.positions = positions;
+    }
+    /// Whether the stream may end here
+    fn at_end(&self) -> bool {
+        self.positions.iter().any(|(section, count)|
+            *count >= self.sections[*section].lines.min && self.sections[section + 1..].iter().all(|later| later.lines.min == 0)
+        )
+    }
+    fn names(&self, sections: &[usize]) -> Vec<String> { sections.iter().map(|i| self.sections[*i].name.to_string()).collect() }
+}
+
+/// Given a stream and its sections, walks each line over the DFAs of the sections it could belong to, writing each
+/// line to stdout as it validates. Lines must appear in section order, with each section spanning its allowed number
+/// of lines. With report_all set, keeps going past failing lines (which are withheld from stdout) and returns every
+/// failure once the stream is exhausted.
+pub fn validate_sections(mut stream: Box<dyn BufRead>, sections: Vec<Section>, framing: Framing, report_all: bool) -> Result<(), MonitorError> {
+    let mut stdout = io::stdout().lock();
+    let mut walk = SectionWalk::new(&sections);
+    let mut failures = Vec::new();
+    let dfas: Vec<&dyn Automaton> = sections.iter().map(|section| section.dfa.as_ref()).collect();
+    let mut validator = StreamValidator::with_automata(dfas, framing)?;
+    let mut candidates = walk.candidates();
+    validator.select(&candidates)?;
+    feed_lines(stream.as_mut(), &mut validator, |validator, verdict| {
+        match verdict {
+            LineVerdict::Valid(line, accepted_by, cr) => {
+                walk.advance(&accepted_by);
+                emit_line(&mut stdout, &line, cr, framing)?;
+            },
+            LineVerdict::Invalid(line_no, failure) => {
+                let failure = ValidationFailure::Expected(Box::new(failure), walk.names(&candidates));
+                if !report_all { return Err(MonitorError::Validation(vec![(line_no, failure)])) }
+                failures.push((line_no, failure));
+            },
+        }
+        candidates = walk.candidates();
+        validator.select(&candidates)
+    })?;
+    //The stream may have ended before every required section showed up
+    if !walk.at_end() {
+        failures.push((validator.line_count() + 1, ValidationFailure::Expected(Box::new(ValidationFailure::Whole(Vec::new())), walk.names(&walk.candidates()))));
+    }
+    if failures.is_empty() { Ok(()) } else { Err(MonitorError::Validation(failures)) }
+}
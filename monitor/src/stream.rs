@@ -0,0 +1,179 @@
+use regex_automata::{dfa::Automaton, util::{primitives::StateID, start::Config}, Anchored};
+use crate::{MonitorError, ValidationFailure};
+
+/// How a `\r` directly before each delimiter is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrlfMode {
+    Strip, //Removed before validation (and from the validated line) wherever present
+    Require, //Lines without one fail, and it's removed before validation
+}
+
+/// How a stream is split into records (lines)
+#[derive(Debug, Clone, Copy)]
+pub struct Framing {
+    pub delimiter: u8,
+    pub crlf: Option<CrlfMode>,
+}
+/// Newline delimited, with a \r before the newline stripped (as `BufRead::lines` does)
+impl Default for Framing {
+    fn default() -> Self { Framing { delimiter: b'\n', crlf: Some(CrlfMode::Strip) } }
+}
+impl Framing {
+    /// Puts back what the framing took off a validated line to write it out again: the \r (if it had one) and the
+    /// delimiter, if the line had one
+    pub(crate) fn restore(&self, line: &mut Vec<u8>, cr: bool, delimited: bool) {
+        if cr { line.push(b'\r'); }
+        if delimited { line.push(self.delimiter); }
+    }
+}
+
+/// The outcome of validating one line of a stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineVerdict {
+    Valid(Vec<u8>, Vec<usize>, bool), //The line as read, less its delimiter and any \r handled by the framing, the indices of the automata that accept it, and whether a \r was taken off
+    Invalid(usize, ValidationFailure), //The line's 1-based line number and why it failed
+}
+
+/// Validates a stream pushed to it in chunks of any size, line by line over one or more automata (a line is valid if
+/// any of them accepts it). Lines may span chunks; a line every automaton dies partway through is failed straight away
+/// and the rest of it skipped rather than buffered.
+pub struct StreamValidator<A: Automaton> {
+    dfas: Vec<A>,
+    framing: Framing,
+    starts: Vec<StateID>,
+    selected: Vec<usize>, //Automata each line is walked over from the next one on
+    walks: Vec<(usize, StateID)>, //Each automaton the current line is walked over, in its current state
+    alive: usize, //How many of them can still match the line
+    matched: bool, //Whether some prefix of the current line has matched (for any of them)
+    line: Vec<u8>, //Every byte of the current line fed to the automata so far
+    started: bool, //Whether any of the current line has been fed (a held \r included)
+    pending_cr: bool, //With CRLF handling, a \r is held back until we know whether it directly precedes the delimiter
+    skipping: bool, //The current line has already failed
+    line_count: usize,
+    empty_line_stream: bool, //Whether an empty stream is validated as a single empty line
+    finished: bool,
+}
+impl<A: Automaton> StreamValidator<A> {
+    /// Validator for newline delimited streams
+    pub fn new(dfa: A) -> Result<Self, MonitorError> { Self::with_framing(dfa, Framing::default()) }
+    pub fn with_framing(dfa: A, framing: Framing) -> Result<Self, MonitorError> { Self::build(vec![dfa], framing, true) }
+    /// Validator walking each line over several automata side by side - every one of them unless `select` narrows
+    /// it down. An empty stream gets no verdict at all: it's up to the caller what a stream of no lines means.
+    pub fn with_automata(dfas: Vec<A>, framing: Framing) -> Result<Self, MonitorError> { Self::build(dfas, framing, false) }
+    fn build(dfas: Vec<A>, framing: Framing, empty_line_stream: bool) -> Result<Self, MonitorError> {
+        let starts = dfas.iter()
+            .map(|dfa| dfa.start_state(&Config::new().anchored(Anchored::Yes)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| MonitorError::Usage(format!("Couldn't bring DFA to its start state: {}", e)))?;
+        let selected = (0..dfas.len()).collect();
+        let mut validator = StreamValidator {
+            dfas, framing, starts, selected, walks: Vec::new(), alive: 0, matched: false, line: Vec::new(), started: false, pending_cr: false,
+            skipping: false, line_count: 0, empty_line_stream, finished: false,
+        };
+        validator.next_line();
+        Ok(validator)
+    }
+    /// Selects the automata (by index) that lines are walked over from the next line that hasn't been fed any bytes
+    /// yet on - the current one, unless it's partway through
+    pub fn select(&mut self, which: &[usize]) -> Result<(), MonitorError> {
+        if let Some(i) = which.iter().find(|i| **i >= self.dfas.len()) {
+            return Err(MonitorError::Usage(format!("Stream validator has no automaton {} (it has {})", i, self.dfas.len())))
+        }
+        self.selected = which.to_vec();
+        if !self.started { self.start_walks(); }
+        Ok(())
+    }
+    /// Validates the next chunk of the stream, returning a verdict for every line it completes (or fails early)
+    pub fn feed(&mut self, mut bytes: &[u8]) -> Result<Vec<LineVerdict>, MonitorError> {
+        let mut verdicts = Vec::new();
+        loop {
+            let (used, verdict) = self.feed_line(bytes)?;
+            bytes = &bytes[used..];
+            match verdict {
+                Some(verdict) => verdicts.push(verdict),
+                None => return Ok(verdicts),
+            }
+        }
+    }
+    /// Validates the chunk up to the first verdict reached in it, returning how many of its bytes were used and the
+    /// verdict (None if the chunk ran out first) - so that `select` can be called in between lines
+    pub fn feed_line(&mut self, bytes: &[u8]) -> Result<(usize, Option<LineVerdict>), MonitorError> {
+        if self.finished { return Err(MonitorError::Usage(String::from("Stream validator fed after the stream was finished"))) }
+        let hold_cr = self.framing.crlf.is_some();
+        for (i, &byte) in bytes.iter().enumerate() {
+            if byte == self.framing.delimiter {
+                let verdict = if self.skipping { None } else { Some(self.end_line()) };
+                self.next_line();
+                if verdict.is_some() { return Ok((i + 1, verdict)) }
+                continue
+            }
+            self.started = true;
+            if self.skipping { continue }
+            let alive = (!self.pending_cr || self.step(b'\r')) && ((hold_cr && byte == b'\r') || self.step(byte));
+            if !alive {
+                let offset = self.line.len() - 1;
+                let line = std::mem::take(&mut self.line);
+                self.line_count += 1;
+                self.skipping = true;
+                let failure = if self.matched { ValidationFailure::Partial(line, Some(offset)) } else { ValidationFailure::Dead(line, offset) };
+                return Ok((i + 1, Some(LineVerdict::Invalid(self.line_count, failure))))
+            }
+            self.pending_cr = hold_cr && byte == b'\r';
+        }
+        Ok((bytes.len(), None))
+    }
+    /// Ends the stream, returning the verdict on its last line if it wasn't delimited. With a single automaton, an
+    /// empty stream fails as a single empty line unless the automaton matches the empty string.
+    pub fn finish(&mut self) -> Result<Vec<LineVerdict>, MonitorError> {
+        if self.finished { return Err(MonitorError::Usage(String::from("Stream validator finished twice"))) }
+        self.finished = true;
+        if self.started && !self.skipping { return Ok(vec![self.end_line()]) }
+        if self.empty_line_stream && self.line_count == 0 && !self.started && !self.selected.iter().any(|i| self.dfas[*i].has_empty()) {
+            return Ok(vec![LineVerdict::Invalid(1, ValidationFailure::Whole(Vec::new()))])
+        }
+        Ok(Vec::new())
+    }
+    /// Number of lines given a verdict so far
+    pub fn line_count(&self) -> usize { self.line_count }
+    pub fn automata(&self) -> &[A] { &self.dfas }
+    pub fn framing(&self) -> Framing { self.framing }
+
+    /// Feeds a byte of the line to every automaton still alive, returning false once none of them can match the
+    /// line. Quit states are treated like the dead state - the automaton can't tell us anything more about the line.
+    fn step(&mut self, byte: u8) -> bool {
+        self.line.push(byte);
+        for (i, state) in self.walks.iter_mut() {
+            let dfa = &self.dfas[*i];
+            if dfa.is_dead_state(*state) || dfa.is_quit_state(*state) { continue }
+            *state = dfa.next_state(*state, byte);
+            if dfa.is_dead_state(*state) || dfa.is_quit_state(*state) { self.alive -= 1; }
+            else { self.matched |= dfa.is_match_state(*state); }
+        }
+        self.alive > 0
+    }
+    fn end_line(&mut self) -> LineVerdict {
+        self.line_count += 1;
+        let line = std::mem::take(&mut self.line);
+        let accepted: Vec<usize> = self.walks.iter()
+            .filter(|(i, state)| self.dfas[*i].is_match_state(self.dfas[*i].next_eoi_state(*state)))
+            .map(|(i, _)| *i)
+            .collect();
+        let failure = if self.framing.crlf == Some(CrlfMode::Require) && !self.pending_cr { ValidationFailure::MissingCr(line) }
+            else if !accepted.is_empty() { return LineVerdict::Valid(line, accepted, self.pending_cr) }
+            else if self.matched { ValidationFailure::Partial(line, None) }
+            else { ValidationFailure::Whole(line) };
+        LineVerdict::Invalid(self.line_count, failure)
+    }
+    fn next_line(&mut self) {
+        self.line.clear();
+        self.started = false;
+        self.pending_cr = false;
+        self.skipping = false;
+        self.start_walks();
+    }
+    fn start_walks(&mut self) {
+        self.walks = self.selected.iter().map(|i| (*i, self.starts[*i])).collect();
+        self.alive = self.walks.len();
+        self.matched = self.walks.iter().any(|(i, state)| self.dfas[*i].is_match_state(*state));
+    }
+}
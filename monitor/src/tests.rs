@@ -1,9 +1,13 @@
 use super::*;
-use regex_automata::dfa::{dense::DFA, Automaton};
-use std::io::{BufReader, BufRead};
+use regex_automata::{dfa::{dense::DFA, Automaton}, util::{primitives::StateID, start::Config}, Anchored, Input};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::collections::HashSet;
 use std::process::{Command, Stdio};
+use monitor::{DfaDefect, TransitionDesc, TransitionTable, ValidatingReader, ValidatingWriter, ValidationFailure};
+use run::{interpose, Event, Pipe};
 
 //Helpers
+#[allow(clippy::zombie_processes)] //The command's output is streamed rather than collected, and it's reaped with the test process
 fn output_stream(raw_cmd: &str) -> Box<dyn BufRead> { 
     let mut command = Command::new("sh")
         .arg("-c").arg(raw_cmd)
@@ -15,51 +19,921 @@ fn dfa_from_pat(pat: &str) -> Box<dyn Automaton> { Box::new(DFA::new(pat).unwrap
     
 //TODO: Write tests for main
 
-//validate_stream tests
+//feed_stream tests
 #[test]
 fn simple_match() {
     let stream = output_stream("echo hello world");
     let dfa = dfa_from_pat("hello world");
     //assert_eq!(validate_stream(stream, dfa), String::from("hello world"));
-    validate_stream(stream, dfa).unwrap();
+    feed_stream(stream, dfa.as_ref(), Framing::default(), false).unwrap();
 }
 #[test]
 #[should_panic]
 fn simple_fail() {
     let stream = output_stream("echo hello world");
     let dfa = dfa_from_pat("helo world");
-    validate_stream(stream, dfa).unwrap();
+    feed_stream(stream, dfa.as_ref(), Framing::default(), false).unwrap();
 }
 #[test]
 fn basic_regex_match() {
     let stream = output_stream("echo hello world.");
     let dfa = dfa_from_pat(r"[a-z]+ [a-z]*\.");
     //assert_eq!(validate_stream(stream, dfa), String::from("hello world."));
-    validate_stream(stream, dfa).unwrap();
+    feed_stream(stream, dfa.as_ref(), Framing::default(), false).unwrap();
 }
 #[test]
 #[should_panic]
 fn basic_regex_fail() {
     let stream = output_stream("echo hello w0rld.");
     let dfa = dfa_from_pat(r"[a-z]+ [a-z]*\.");
-    validate_stream(stream, dfa).unwrap();
+    feed_stream(stream, dfa.as_ref(), Framing::default(), false).unwrap();
 }
 #[test]
 fn complex_regex_match() {
     let stream = output_stream("ls -l");
     let dfa = dfa_from_pat(r"(total [0-9]+)|([drwxr@-]+ +[0-9]+ +[^ ]+ +[^ ]+ +[0-9]+ +[a-zA-Z]+ +[0-9]+ +[0-9:]+ +.+)");
-    validate_stream(stream, dfa).unwrap();
+    feed_stream(stream, dfa.as_ref(), Framing::default(), false).unwrap();
 }
 #[test]
 #[should_panic]
 fn complex_regex_fail() {
     let stream = output_stream("ps -f");
     let dfa = dfa_from_pat(r"(UID( )+PID( )+PPID( )+C( )+STIME( )+TTY( )+TIME( )+CMD)|(([0-9a-zA-Z_]+|-)( )+[0-9]+( )+[0-9]+( )+[0-9]+( )+[0-9]+( )+[a-z0-9/?]+[^ ]+[0-9][0-9:]+( )+.+)");
-    validate_stream(stream, dfa).unwrap();
+    feed_stream(stream, dfa.as_ref(), Framing::default(), false).unwrap();
 }
 #[test]
 fn complex_stream() {
     let stream = output_stream("ifconfig | grep 'inet ' | grep -v 127.0.0.1 | cut -f  2");
     let dfa = dfa_from_pat(r"^ *(~(inet +)|(inet +([0-9]+\.){3}[0-9]+)).*");
-    validate_stream(stream, dfa).unwrap();
-}
\ No newline at end of file
+    feed_stream(stream, dfa.as_ref(), Framing::default(), false).unwrap();
+}
+//Dfa tests
+fn example_dfa() -> Dfa { Dfa::deserialize_from_json(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../json-to-dfa/example_dfa.json")).unwrap() }
+#[test]
+fn dense_table_matches_sparse() {
+    let dfa = example_dfa();
+    for line in ["abcdA234", "A1", "zA99", "A", "abc", "A12", "", "aA0", "\u{ff}A1"] {
+        let dense = dfa.try_search_fwd(&Input::new(line)).unwrap();
+        let sparse = dfa.as_sparse().try_search_fwd(&Input::new(line)).unwrap();
+        assert_eq!(dense, sparse, "Dense and sparse searches disagree on {:?}", line);
+    }
+}
+#[test]
+fn dense_table_compresses_alphabet() {
+    let dfa = example_dfa();
+    //[a-z], A, 1, [2-9] and everything else
+    assert_eq!(dfa.alphabet_len(), 8);
+    assert_eq!(dfa.state_len(), 5);
+    feed_stream(Box::new(Cursor::new("abcdA234\nA1\n")), &dfa, Framing::default(), false).unwrap();
+}
+
+//feed_stream tests, reporting every failure
+#[test]
+fn report_all_match() {
+    let stream = output_stream("printf 'hello\nworld\n'");
+    let dfa = dfa_from_pat("^[a-z]+$");
+    feed_stream(stream, dfa.as_ref(), Framing::default(), true).unwrap();
+}
+#[test]
+fn report_all_collects_every_failure() {
+    let stream = output_stream("printf 'hello\nw0rld\nagain\nhello!\n'");
+    let dfa = dfa_from_pat("[a-z]+");
+    let MonitorError::Validation(failures) = feed_stream(stream, dfa.as_ref(), Framing::default(), true).unwrap_err() else { panic!("Expected a validation failure") };
+    let failures: Vec<(usize, String)> = failures.into_iter().map(|(line_no, failure)| match failure {
+        ValidationFailure::Partial(line, _) => (line_no, format!("partial {}", String::from_utf8_lossy(&line))),
+        ValidationFailure::Whole(line) => (line_no, format!("whole {}", String::from_utf8_lossy(&line))),
+        failure => (line_no, format!("{:?}", failure)),
+    }).collect();
+    //Lines that die after a prefix matched fail there (regex-automata's DFAs report matches a byte late)
+    assert_eq!(failures, vec![(2, String::from("partial w0r")), (4, String::from("partial hello!"))]);
+}
+#[test]
+fn report_all_fails_unmatched_lines_early() {
+    //Lines none of which ever matched fail at the byte that kills the DFA
+    let stream = output_stream("printf 'hello\nw0rld\nagain\nhello!\n'");
+    let MonitorError::Validation(failures) = feed_stream(stream, &*dfa_from_pat("^[a-z]+$"), Framing::default(), true).unwrap_err() else { panic!("Expected a validation failure") };
+    assert_eq!(failures, vec![(2, ValidationFailure::Dead(b"w0".to_vec(), 1)), (4, ValidationFailure::Dead(b"hello!".to_vec(), 5))]);
+}
+
+//StreamValidator tests
+#[test]
+fn stream_validator_chunking() {
+    let stream = b"A1\r\nabc\nA12345\r\nab12\nabA\r\nabcA7";
+    let framing = Framing { delimiter: b'\n', crlf: Some(CrlfMode::Strip) };
+    let whole = {
+        let mut validator = StreamValidator::with_framing(example_dfa(), framing).unwrap();
+        let mut verdicts = validator.feed(stream).unwrap();
+        verdicts.extend(validator.finish().unwrap());
+        verdicts
+    };
+    assert_eq!(whole, vec![
+        LineVerdict::Valid(b"A1".to_vec(), vec![0], true),
+        LineVerdict::Invalid(2, ValidationFailure::Whole(b"abc".to_vec())),
+        LineVerdict::Invalid(3, ValidationFailure::Partial(b"A12".to_vec(), Some(2))),
+        LineVerdict::Invalid(4, ValidationFailure::Dead(b"ab1".to_vec(), 2)),
+        LineVerdict::Invalid(5, ValidationFailure::Whole(b"abA".to_vec())),
+        LineVerdict::Valid(b"abcA7".to_vec(), vec![0], false),
+    ]);
+    //Lines (and held back \r's) split across chunks come out the same
+    for size in 1..stream.len() {
+        let mut validator = StreamValidator::with_framing(example_dfa(), framing).unwrap();
+        let mut verdicts: Vec<LineVerdict> = stream.chunks(size).flat_map(|chunk| validator.feed(chunk).unwrap()).collect();
+        verdicts.extend(validator.finish().unwrap());
+        assert_eq!(verdicts, whole, "chunks of {} bytes", size);
+    }
+}
+#[test]
+fn stream_validator_edge_cases() {
+    //Empty streams only pass if the DFA matches the empty string
+    let mut validator = StreamValidator::new(example_dfa()).unwrap();
+    assert_eq!(validator.finish().unwrap(), vec![LineVerdict::Invalid(1, ValidationFailure::Whole(Vec::new()))]);
+    assert!(validator.feed(b"A1\n").is_err() && validator.finish().is_err());
+    assert_eq!(StreamValidator::new(Dfa::from_regex("A*").unwrap()).unwrap().finish().unwrap(), vec![]);
+    //A trailing delimiter doesn't start another line, but a lone one is an empty line
+    let mut validator = StreamValidator::new(Dfa::from_regex("A*").unwrap()).unwrap();
+    assert_eq!(validator.feed(b"\nAA\n").unwrap(), vec![LineVerdict::Valid(Vec::new(), vec![0], false), LineVerdict::Valid(b"AA".to_vec(), vec![0], false)]);
+    assert_eq!(validator.finish().unwrap(), vec![]);
+    assert_eq!(validator.line_count(), 2);
+    //Lines are failed as soon as the DFA dies, before their delimiter arrives
+    let mut validator = StreamValidator::new(DFA::new("^[a-z]+$").unwrap()).unwrap();
+    assert_eq!(validator.feed(b"ab1xyz").unwrap(), vec![LineVerdict::Invalid(1, ValidationFailure::Dead(b"ab1".to_vec(), 2))]);
+    assert_eq!(validator.feed(b"zz\nok").unwrap(), vec![]);
+    assert_eq!(validator.finish().unwrap(), vec![LineVerdict::Valid(b"ok".to_vec(), vec![0], false)]);
+    //So are lines that had a matching prefix, without buffering the rest of them
+    let mut validator = StreamValidator::new(Dfa::from_regex("[0-9]+").unwrap()).unwrap();
+    assert_eq!(validator.feed(b"1x").unwrap(), vec![LineVerdict::Invalid(1, ValidationFailure::Partial(b"1x".to_vec(), Some(1)))]);
+    assert_eq!(validator.feed(&[b'x'; 4096]).unwrap(), vec![]);
+    assert_eq!(validator.feed(b"
+12
+").unwrap(), vec![LineVerdict::Valid(b"12".to_vec(), vec![0], false)]);
+    //A missing \r is only known once the line ends
+    let mut validator = StreamValidator::with_framing(DFA::new("^[a-z]+$").unwrap(), Framing { delimiter: b'\n', crlf: Some(CrlfMode::Require) }).unwrap();
+    assert_eq!(validator.feed(b"ab\r\ncd").unwrap(), vec![LineVerdict::Valid(b"ab".to_vec(), vec![0], true)]);
+    assert_eq!(validator.finish().unwrap(), vec![LineVerdict::Invalid(2, ValidationFailure::MissingCr(b"cd".to_vec()))]);
+}
+#[test]
+fn stream_validator_several_automata() {
+    let dfas = vec![DFA::new("^[a-z]+$").unwrap(), DFA::new("^[a-c0-9]+$").unwrap()];
+    let mut validator = StreamValidator::with_automata(dfas, Framing::default()).unwrap();
+    assert_eq!(validator.feed(b"abc\nxyz\n123\n").unwrap(), vec![
+        LineVerdict::Valid(b"abc".to_vec(), vec![0, 1], false),
+        LineVerdict::Valid(b"xyz".to_vec(), vec![0], false),
+        LineVerdict::Valid(b"123".to_vec(), vec![1], false),
+    ]);
+    //Selecting partway through a line applies from the next one on
+    validator.select(&[0]).unwrap();
+    assert_eq!(validator.feed_line(b"12").unwrap(), (1, Some(LineVerdict::Invalid(4, ValidationFailure::Dead(b"1".to_vec(), 0)))));
+    validator.select(&[1]).unwrap();
+    assert_eq!(validator.feed(b"2\n45\n").unwrap(), vec![LineVerdict::Valid(b"45".to_vec(), vec![1], false)]);
+    assert!(validator.select(&[2]).is_err());
+    //An empty stream is no lines at all
+    let mut validator = StreamValidator::with_automata(vec![DFA::new("^a$").unwrap()], Framing::default()).unwrap();
+    assert_eq!(validator.finish().unwrap(), vec![]);
+}
+
+#[test]
+fn validating_writer() {
+    let mut writer = ValidatingWriter::new(Vec::new(), example_dfa()).unwrap();
+    writer.write_all(b"A1\nabcA").unwrap();
+    //Lines are held back until they're complete
+    assert_eq!(writer.get_ref(), b"A1\n");
+    writer.write_all(b"7\nA").unwrap();
+    assert_eq!(writer.get_ref(), b"A1\nabcA7\n");
+    writer.write_all(b"2").unwrap();
+    assert_eq!(writer.finish().unwrap(), b"A1\nabcA7\nA2");
+    //The failing line is never written, and nothing after it is
+    let mut writer = ValidatingWriter::new(Vec::new(), example_dfa()).unwrap();
+    let err = writer.write_all(b"A1\nab1\nA3\n").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let MonitorError::Validation(failures) = MonitorError::from(err) else { panic!("Expected a validation failure") };
+    assert_eq!(failures, vec![(2, ValidationFailure::Dead(b"ab1".to_vec(), 2))]);
+    assert!(writer.write_all(b"A4\n").is_err());
+    assert_eq!(writer.get_ref(), b"A1\n");
+    assert!(writer.finish().is_err());
+    //An unfinished last line is only validated by finish
+    let mut writer = ValidatingWriter::new(Vec::new(), example_dfa()).unwrap();
+    writer.write_all(b"A1\nabc").unwrap();
+    assert!(matches!(MonitorError::from(writer.finish().unwrap_err()), MonitorError::Validation(_)));
+    //CRLF lines validate without their \r, but are passed on with it
+    let mut writer = ValidatingWriter::new(Vec::new(), example_dfa()).unwrap();
+    writer.write_all(b"A1\r\nabcA7\nA2\r").unwrap();
+    assert_eq!(writer.finish().unwrap(), b"A1\r\nabcA7\nA2\r");
+}
+#[test]
+fn validating_reader() {
+    let framing = Framing { delimiter: b'\n', crlf: Some(CrlfMode::Require) };
+    let mut output = String::new();
+    ValidatingReader::with_framing("A1\r\nabcA7\r\nA2\r".as_bytes(), example_dfa(), framing).unwrap().read_to_string(&mut output).unwrap();
+    assert_eq!(output, "A1\r\nabcA7\r\nA2\r");
+    let err = ValidatingReader::with_framing("A1\r\nA2".as_bytes(), example_dfa(), framing).unwrap().lines().nth(1).unwrap().unwrap_err();
+    assert!(matches!(MonitorError::from(err), MonitorError::Validation(failures) if failures == [(2, ValidationFailure::MissingCr(b"A2".to_vec()))]));
+    let lines: Vec<String> = ValidatingReader::new(Cursor::new("A1\nabcA7\n"), example_dfa()).unwrap().lines().collect::<io::Result<_>>().unwrap();
+    assert_eq!(lines, ["A1", "abcA7"]);
+    let mut output = Vec::new();
+    ValidatingReader::new(Cursor::new("A1\r\nabcA7\nA2\r\n"), example_dfa()).unwrap().read_to_end(&mut output).unwrap();
+    assert_eq!(output, b"A1\r\nabcA7\nA2\r\n");
+    //Lines before the failing one are still read
+    let mut reader = ValidatingReader::new(Cursor::new("A1\nA2\nabA\nA3\n"), example_dfa()).unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "A1\nA2\n");
+    let err = reader.read_line(&mut line).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "Validation failed on line 3\nIncriminating line: abA");
+    assert!(reader.read_line(&mut line).is_err());
+    //So is an empty stream its DFA rejects
+    assert!(ValidatingReader::new(io::empty(), example_dfa()).unwrap().read_to_end(&mut Vec::new()).is_err());
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_validating_reader() {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+    use monitor::AsyncValidatingReader;
+    //A small duplex buffer splits lines across reads
+    let (mut tx, rx) = tokio::io::duplex(3);
+    tokio::spawn(async move { tx.write_all(b"A1\nabcA7\nA2").await });
+    let mut output = String::new();
+    AsyncValidatingReader::new(rx, example_dfa()).unwrap().read_to_string(&mut output).await.unwrap();
+    assert_eq!(output, "A1\nabcA7\nA2");
+    let (mut tx, rx) = tokio::io::duplex(3);
+    tokio::spawn(async move { tx.write_all(b"A1\nabA\nA2\n").await });
+    let mut reader = AsyncValidatingReader::new(rx, example_dfa()).unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "A1\n");
+    let err = reader.read_line(&mut line).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(matches!(MonitorError::from(err), MonitorError::Validation(failures) if failures == [(2, ValidationFailure::Whole(b"abA".to_vec()))]));
+}
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_validated_lines() {
+    use std::{future::poll_fn, pin::Pin};
+    use futures_core::Stream;
+    use tokio::io::AsyncWriteExt;
+    use monitor::ValidatedLines;
+    let (mut tx, rx) = tokio::io::duplex(3);
+    tokio::spawn(async move { tx.write_all(b"A1\r\nabcA7\nab12345\nA2\n").await });
+    let mut lines = ValidatedLines::with_framing(rx, example_dfa(), Framing { delimiter: b'\n', crlf: Some(CrlfMode::Strip) }).unwrap();
+    let mut next = async || poll_fn(|cx| Pin::new(&mut lines).poll_next(cx)).await;
+    assert_eq!(next().await.unwrap().unwrap(), "A1");
+    assert_eq!(next().await.unwrap().unwrap(), "abcA7");
+    let Some(Err(MonitorError::Validation(failures))) = next().await else { panic!("Expected a validation failure") };
+    assert_eq!(failures, [(3, ValidationFailure::Dead(b"ab1".to_vec(), 2))]);
+    assert!(next().await.is_none());
+}
+
+//Error tests
+#[test]
+fn missing_dfa_file() {
+    let err = Dfa::deserialize(PathBuf::from("does/not/exist.bc")).err().unwrap();
+    assert_eq!(err.exit_code(), MonitorError::DFA_LOAD_EXIT_CODE);
+}
+#[test]
+fn failure_line_numbers() {
+    let stream = output_stream("printf 'hello\nhello\nhelo\n'");
+    let dfa = dfa_from_pat("hello");
+    let err = feed_stream(stream, dfa.as_ref(), Framing::default(), false).unwrap_err();
+    assert_eq!(err.exit_code(), MonitorError::VALIDATION_EXIT_CODE);
+    assert_eq!(err.to_string(), "Validation failed at byte 3 of line 3\nIncriminating line (up to failure): helo");
+}
+
+//Early failure tests
+#[test]
+fn early_failure_on_endless_line() {
+    //The second line never ends, so the monitor must give up on it as soon as the DFA dies
+    let stream = Box::new(BufReader::new(Cursor::new("hello\nab1").chain(io::repeat(b'x'))));
+    let err = feed_stream(stream, &*dfa_from_pat("^[a-z]+$"), Framing::default(), false).unwrap_err();
+    let MonitorError::Validation(failures) = err else { panic!("Expected a validation failure") };
+    assert_eq!(failures, vec![(2, ValidationFailure::Dead(b"ab1".to_vec(), 2))]);
+}
+#[test]
+fn early_failure_skips_rest_of_line() {
+    let stream = output_stream("printf 'A1\nab12345\nabA\nabcA7\n'");
+    let MonitorError::Validation(failures) = feed_stream(stream, &example_dfa(), Framing::default(), true).unwrap_err() else { panic!("Expected a validation failure") };
+    assert_eq!(failures, vec![(2, ValidationFailure::Dead(b"ab1".to_vec(), 2)), (3, ValidationFailure::Whole(b"abA".to_vec()))]);
+}
+#[test]
+fn crlf_line_endings_stripped() {
+    let stream = output_stream("printf 'hello\r\nworld\r\n'");
+    feed_stream(stream, &*dfa_from_pat("^[a-z]+$"), Framing::default(), false).unwrap();
+}
+#[test]
+fn crlf_line_endings_stripped_wherever_present() {
+    let stream = output_stream("printf 'hello\r\nworld\nagain\r\n'");
+    feed_stream(stream, &*dfa_from_pat("^[a-z]+$"), Framing { delimiter: b'\n', crlf: Some(CrlfMode::Strip) }, false).unwrap();
+}
+#[test]
+fn crlf_line_endings_stripped_are_emitted_again() {
+    //Validated lines go out as they came in, whichever lines had a \r
+    let stream = b"hello\r\nworld\nagain\r\nend\r";
+    let framing = Framing::default();
+    let mut validator = StreamValidator::with_framing(DFA::new("^[a-z]+$").unwrap(), framing).unwrap();
+    let mut verdicts = validator.feed(stream).unwrap();
+    verdicts.extend(validator.finish().unwrap());
+    let mut output = Vec::new();
+    for verdict in verdicts {
+        let LineVerdict::Valid(line, _, cr) = verdict else { panic!("Expected {:?} to be valid", verdict) };
+        emit_line(&mut output, &line, cr, framing).unwrap();
+    }
+    assert_eq!(output, b"hello\r\nworld\nagain\r\nend\r\n");
+}
+#[test]
+fn crlf_line_endings_kept() {
+    let stream = output_stream("printf 'hello\r\n'");
+    feed_stream(stream, &*dfa_from_pat("^[a-z]+$"), Framing { delimiter: b'\n', crlf: None }, false).unwrap_err();
+}
+#[test]
+fn crlf_line_endings_required() {
+    let stream = output_stream("printf 'hello\r\nworld\n'");
+    let framing = Framing { delimiter: b'\n', crlf: Some(CrlfMode::Require) };
+    let MonitorError::Validation(failures) = feed_stream(stream, &*dfa_from_pat("^[a-z]+$"), framing, true).unwrap_err() else { panic!("Expected a validation failure") };
+    assert_eq!(failures, vec![(2, ValidationFailure::MissingCr(b"world".to_vec()))]);
+}
+#[test]
+fn null_delimited_records() {
+    let stream = output_stream("printf './a b\\0./c\\nd\\0'");
+    feed_stream(stream, &*dfa_from_pat(r"^\./[a-z \n]+$"), Framing { delimiter: b'\0', crlf: None }, false).unwrap();
+}
+#[test]
+fn delimiter_parsing() {
+    assert_eq!(parse_delimiter(","), Ok(b','));
+    assert_eq!(parse_delimiter("\\t"), Ok(b'\t'));
+    assert_eq!(parse_delimiter("0x1e"), Ok(0x1e));
+    assert_eq!(parse_delimiter("\\x00"), Ok(0));
+    parse_delimiter("ab").unwrap_err();
+}
+
+//Binary input tests
+#[test]
+fn non_utf8_lines() {
+    //Latin-1 encoded "café" - not valid UTF-8
+    let stream = Box::new(Cursor::new(b"caf\xe9\nbar\n".to_vec()));
+    feed_stream(stream, &regex_dfa(r"(?-u)^[a-z\xe0-\xff]+$").unwrap(), Framing::default(), false).unwrap();
+}
+#[test]
+fn non_utf8_failure_rendering() {
+    let stream = Box::new(Cursor::new(b"caf\xe9!\n".to_vec()));
+    let err = feed_stream(stream, &regex_dfa(r"(?-u)^[a-z\xe0-\xff]+$").unwrap(), Framing::default(), false).unwrap_err();
+    assert_eq!(err.to_string(), "Validation failed at byte 4 of line 1\nIncriminating line (up to failure): caf\\xe9!");
+    assert_eq!(err.display(LineRendering::Lossy).to_string(), "Validation failed at byte 4 of line 1\nIncriminating line (up to failure): caf\u{fffd}!");
+}
+
+//Multi-section tests
+fn ps_sections(body_lines: &str, footer: Option<&str>) -> Vec<Section> {
+    let mut sections = vec![
+        Section { name: "header", dfa: load_type("regex:UID +PID +CMD").unwrap(), lines: LineCount::parse("1").unwrap() },
+        Section { name: "body", dfa: load_type("[a-z]+ +[0-9]+ +[a-z]+").unwrap(), lines: LineCount::parse(body_lines).unwrap() },
+    ];
+    if let Some(footer) = footer { sections.push(Section { name: "footer", dfa: load_type(footer).unwrap(), lines: LineCount::parse("1").unwrap() }); }
+    sections
+}
+#[test]
+fn sections_match() {
+    let stream = output_stream("printf 'UID PID CMD\nroot 1 init\nme 42 sh\n'");
+    validate_sections(stream, ps_sections("1..", None), Framing::default(), false).unwrap();
+}
+#[test]
+fn sections_header_out_of_order() {
+    let stream = output_stream("printf 'UID PID CMD\nroot 1 init\nUID PID CMD\nme 42 sh\n'");
+    let MonitorError::Validation(failures) = validate_sections(stream, ps_sections("0..", None), Framing::default(), true).unwrap_err() else { panic!("Expected a validation failure") };
+    assert_eq!(failures, vec![(3, ValidationFailure::Expected(Box::new(ValidationFailure::Dead(b"U".to_vec(), 0)), vec![String::from("body")]))]);
+}
+#[test]
+fn sections_line_counts() {
+    let stream = output_stream("printf 'UID PID CMD\nroot 1 init\nme 42 sh\nme 43 sh\n'");
+    let err = validate_sections(stream, ps_sections("1..2", None), Framing::default(), false).unwrap_err();
+    assert_eq!(err.to_string(), "Validation failed at byte 0 of line 4\nIncriminating line (up to failure): m\nExpected: end of stream");
+}
+#[test]
+fn sections_footer_may_match_body() {
+    //The footer also matches the body's type, so which section the last line belongs to is only known at EOF
+    let stream = output_stream("printf 'UID PID CMD\nroot 1 init\nme 42 sh\n'");
+    validate_sections(stream, ps_sections("1..", Some("[a-z]+ +[0-9]+ +[a-z]+")), Framing::default(), false).unwrap();
+}
+#[test]
+fn sections_missing_footer() {
+    let stream = output_stream("printf 'UID PID CMD\nroot 1 init\n'");
+    let err = validate_sections(stream, ps_sections("1..", Some("total [0-9]+")), Framing::default(), false).unwrap_err();
+    assert_eq!(err.to_string(), "Validation failed on line 3\nIncriminating line: \nExpected: body or footer");
+}
+#[test]
+fn line_count_parsing() {
+    assert_eq!(LineCount::parse("2"), Ok(LineCount { min: 2, max: Some(2) }));
+    assert_eq!(LineCount::parse("1.."), Ok(LineCount { min: 1, max: None }));
+    assert_eq!(LineCount::parse("..3"), Ok(LineCount { min: 0, max: Some(3) }));
+    LineCount::parse("3..1").unwrap_err();
+}
+
+//Line DFA tests
+fn example_line_dfa() -> LineDfa { LineDfa::deserialize_from_json(PathBuf::from("../json-to-dfa/example_line_dfa.json")).unwrap() }
+#[test]
+fn line_dfa_match() {
+    let stream = output_stream("printf 'inet 10.0.0.1\nether aa:bb\nether cc\ninet 1.2\nether 00\n'");
+    validate_line_classes(stream, &example_line_dfa(), Framing::default(), false).unwrap();
+}
+#[test]
+fn line_dfa_unexpected_class() {
+    let stream = output_stream("printf 'inet 10.0.0.1\ninet 1.2\n'");
+    let err = validate_line_classes(stream, &example_line_dfa(), Framing::default(), false).unwrap_err();
+    assert_eq!(err.to_string(), "Validation failed at byte 0 of line 2\nIncriminating line (up to failure): i\nExpected: ether");
+}
+#[test]
+fn line_dfa_report_all() {
+    let stream = output_stream("printf 'ether aa\ninet 10.0.0.1\nether zz\nether bb\ninet 1.2\n'");
+    let MonitorError::Validation(failures) = validate_line_classes(stream, &example_line_dfa(), Framing::default(), true).unwrap_err() else { panic!("Expected a validation failure") };
+    let lines: Vec<usize> = failures.iter().map(|(line_no, _)| *line_no).collect();
+    assert_eq!(lines, vec![1, 3, 6]);
+    assert_eq!(failures[2].1, ValidationFailure::Expected(Box::new(ValidationFailure::Whole(Vec::new())), vec![String::from("ether")]));
+}
+#[test]
+fn line_dfa_expected_classes() {
+    let line_dfa = example_line_dfa();
+    let start = line_dfa.start_state();
+    assert_eq!(line_dfa.expected(start), vec![0]);
+    let block = line_dfa.next_state(line_dfa.next_state(start, 0), 1);
+    assert_eq!(line_dfa.expected(block), vec![0, 1]);
+    assert!(line_dfa.is_accepting(start) && line_dfa.is_accepting(block));
+}
+
+//Field mode tests
+fn route_fields(count: &str, rest: bool) -> Fields {
+    Fields {
+        columns: vec![load_type("default|[0-9./]+").unwrap(), load_type("via|dev").unwrap()],
+        rest: if rest { Some(load_type(".*").unwrap()) } else { None },
+        separator: None,
+        count: LineCount::parse(count).unwrap(),
+    }
+}
+#[test]
+fn fields_rest_of_line() {
+    let stream = output_stream("printf 'default via 10.0.0.1 dev eth0\n  10.0.0.0/24\tdev eth0 proto kernel\n'");
+    validate_fields(stream, route_fields("2", true), Framing::default(), false).unwrap();
+}
+#[test]
+fn fields_failing_column() {
+    let stream = output_stream("printf 'default via 10.0.0.1\n10.0.0.0/24 src 10.0.0.5\n'");
+    let err = validate_fields(stream, route_fields("2", true), Framing::default(), false).unwrap_err();
+    assert_eq!(err.to_string(), "Validation failed on column 2 of line 2\nIncriminating value: src\nIncriminating line: 10.0.0.0/24 src 10.0.0.5");
+}
+#[test]
+fn fields_column_count() {
+    let stream = output_stream("printf 'default via\n10.0.0.0/24 dev dev\ndefault\n'");
+    let MonitorError::Validation(failures) = validate_fields(stream, route_fields("2..3", false), Framing::default(), true).unwrap_err() else { panic!("Expected a validation failure") };
+    assert_eq!(failures, vec![(3, ValidationFailure::ColumnCount(b"default".to_vec(), 1))]);
+}
+#[test]
+fn fields_separator() {
+    let fields = Fields { columns: vec![load_type("[a-z]*").unwrap()], rest: None, separator: Some(b','), count: LineCount::parse("3").unwrap() };
+    let stream = output_stream("printf 'a,,c\na,b,C\n'");
+    let err = validate_fields(stream, fields, Framing::default(), false).unwrap_err();
+    assert!(matches!(err, MonitorError::Validation(ref failures) if failures == &vec![(2, ValidationFailure::Field(b"a,b,C".to_vec(), 3, b"C".to_vec()))]));
+}
+#[test]
+fn fields_empty_stream() {
+    //An empty stream only passes if the fields accept an empty line
+    let err = validate_fields(Box::new(io::empty()), route_fields("2", true), Framing::default(), false).unwrap_err();
+    assert!(matches!(err, MonitorError::Validation(ref failures) if failures == &vec![(1, ValidationFailure::ColumnCount(Vec::new(), 0))]));
+    validate_fields(Box::new(io::empty()), route_fields("0..2", false), Framing::default(), false).unwrap();
+}
+
+//Regex compiler tests
+fn accepts(dfa: &Dfa, line: &str) -> bool { dfa.matches(line.as_bytes()) }
+#[test]
+fn compile_matches_json_dfa() {
+    let dfa = Dfa::from_regex("[a-z]*A(1|[2-9]+)").unwrap();
+    assert_eq!(dfa.state_len(), example_dfa().state_len());
+    for line in ["abcdA234", "A1", "zA99", "A", "abc", "A12", "", "aA0"] {
+        assert_eq!(accepts(&dfa, line), accepts(&example_dfa(), line), "{:?}", line);
+    }
+}
+#[test]
+fn compile_brics_syntax() {
+    let complement = Dfa::from_regex(r" *(~(inet +)|(inet +([0-9]+\.){3}[0-9]+)).*").unwrap();
+    assert!(accepts(&complement, "  inet 10.0.0.1 netmask") && accepts(&complement, "ether aa:bb"));
+    let intersection = Dfa::from_regex("[a-z]+&~(.*bad.*)").unwrap();
+    assert!(accepts(&intersection, "good") && !accepts(&intersection, "notbad") && !accepts(&intersection, ""));
+    let misc = Dfa::from_regex(r#""a.b"@<\t>[^0-9]{2,3}()"#).unwrap();
+    assert!(accepts(&misc, "a.bxyz<t>ab") && !accepts(&misc, "axbxyz<t>ab") && !accepts(&misc, "a.b<t>a1"));
+    let empty = Dfa::from_regex("#").unwrap();
+    assert!(!accepts(&empty, "") && !accepts(&empty, "#"));
+}
+#[test]
+fn compile_errors() {
+    for regex in ["(ab", "a{x}", "[z-a]", "a|", "\"ab"] {
+        assert!(matches!(Dfa::from_regex(regex), Err(MonitorError::RegexCompile { .. })), "{:?}", regex);
+    }
+}
+#[test]
+fn minimize_merges_and_prunes_states() {
+    let sid = StateID::must;
+    let table = TransitionTable::from([
+        (sid(1), vec![TransitionDesc::Match(b'a', sid(2)), TransitionDesc::Match(b'b', sid(3)), TransitionDesc::Match(b'c', sid(5))]),
+        (sid(2), vec![TransitionDesc::Range(b'0', b'9', sid(2))]),
+        (sid(3), vec![TransitionDesc::Range(b'0', b'4', sid(3)), TransitionDesc::Range(b'5', b'9', sid(2))]),
+        (sid(4), vec![TransitionDesc::Match(b'a', sid(1))]), //Unreachable
+        (sid(5), vec![TransitionDesc::Range(0, 255, sid(5))]), //Can never match
+    ]);
+    let dfa = Dfa::new(sid(1), HashSet::from([sid(2), sid(3)]), table).unwrap();
+    let minimized = dfa.minimize();
+    assert_eq!((dfa.state_len(), minimized.state_len()), (6, 3));
+    for line in ["a", "b19", "a5", "c", "cc", "", "b1x", "d"] {
+        assert_eq!(accepts(&minimized, line), accepts(&dfa, line), "{:?}", line);
+    }
+    assert_eq!(example_dfa().minimize().state_len(), example_dfa().state_len());
+}
+
+//DFA validation tests
+#[test]
+fn validate_reports_defects() {
+    let sid = StateID::must;
+    let table = TransitionTable::from([
+        (sid(1), vec![TransitionDesc::Range(b'a', b'z', sid(2)), TransitionDesc::Match(b'd', sid(3)), TransitionDesc::Match(b'0', sid(7))]),
+        (sid(2), vec![]),
+        (sid(4), vec![TransitionDesc::Range(0, 255, sid(2))]),
+    ]);
+    let defects = Dfa::new(sid(1), HashSet::from([sid(2), sid(5)]), table).unwrap().validate().unwrap_err();
+    assert_eq!(defects, vec![
+        DfaDefect::UnknownMatchState(sid(5)),
+        DfaDefect::UnsortedRanges(sid(1)),
+        DfaDefect::OverlappingRanges(sid(1), (b'a', b'z'), (b'd', b'd')),
+        DfaDefect::DanglingTarget(sid(1), sid(3)),
+        DfaDefect::DanglingTarget(sid(1), sid(7)),
+        DfaDefect::UnreachableState(sid(4)),
+    ]);
+    example_dfa().validate().unwrap();
+    Dfa::from_regex("(a|b)*c").unwrap().validate().unwrap();
+}
+#[test]
+fn invalid_json_dfa_fails_to_load() {
+    let path = env::temp_dir().join(format!("monitor-invalid-dfa-{}.json", std::process::id()));
+    fs::write(&path, r#"{"start_state": 0, "match_states": [2], "transition_table": [{"curr_state": 1, "range_start": 0, "range_end": 9, "next_state": 2}]}"#).unwrap();
+    let err = Dfa::deserialize_from_json(path.clone()).err().expect("Expected the DFA to be rejected");
+    fs::remove_file(&path).unwrap();
+    assert_eq!(err.exit_code(), MonitorError::DFA_LOAD_EXIT_CODE);
+    assert!(matches!(err, MonitorError::InvalidDfa { ref defects, .. } if defects == &vec![DfaDefect::DeadStateMisuse(StateID::ZERO, "is the start state")]));
+}
+#[test]
+fn baseline_serialized_dfa_loads() {
+    //Serialized before DFAs were validated on load, with a match state that's only ever a transition target
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../json-to-dfa/serialized_example_dfa.bc");
+    let dfa = Dfa::deserialize(path).unwrap();
+    assert!(dfa.equivalent(&example_dfa()).is_ok());
+    feed_stream(Box::new(Cursor::new("A1\nabcA7\n")), &dfa, Framing::default(), false).unwrap();
+}
+#[test]
+fn bad_dfa_descriptions_are_errors() {
+    let sid = StateID::must;
+    //The dead state's ID is reserved
+    let table = TransitionTable::from([(sid(0), vec![]), (sid(1), vec![TransitionDesc::Match(b'a', sid(1))])]);
+    assert_eq!(Dfa::new(sid(1), HashSet::from([sid(1)]), table).err(), Some(vec![DfaDefect::DeadStateMisuse(sid(0), "has transitions leading out of it")]));
+    //State IDs too large to be StateIDs
+    let path = env::temp_dir().join(format!("monitor-huge-state-dfa-{}.json", std::process::id()));
+    fs::write(&path, r#"{"start_state": 1, "match_states": [4294967295], "transition_table": [{"curr_state": 1, "range_start": 0, "range_end": 9, "next_state": 4294967295}]}"#).unwrap();
+    let err = Dfa::deserialize_from_json(path.clone()).err().expect("Expected the DFA to be rejected");
+    fs::remove_file(&path).unwrap();
+    assert!(matches!(err, MonitorError::InvalidDfa { ref defects, .. } if defects == &vec![DfaDefect::StateIdTooLarge(4294967295)]));
+    //The sparse search treats states without a table entry as having no transitions
+    let dfa = Dfa::from_regex("ab").unwrap();
+    let sparse = dfa.as_sparse();
+    assert!(sparse.is_dead_state(sparse.next_state(sid(1000), b'a')));
+}
+
+//Set operation tests
+#[test]
+fn dfa_set_operations() {
+    let words = Dfa::from_regex("[a-z]+").unwrap();
+    let bad = Dfa::from_regex(".*bad.*").unwrap();
+    for (dfa, expected) in [
+        (words.intersect(&bad), [false, true, false, false]),
+        (words.union(&bad), [true, true, true, false]),
+        (words.difference(&bad), [true, false, false, false]),
+        (words.complement(), [false, false, true, true]),
+    ] {
+        let got: Vec<bool> = ["good", "notbad", "bad!", "A1"].iter().map(|line| accepts(&dfa, line)).collect();
+        assert_eq!(got, expected);
+    }
+    assert!(accepts(&words.complement(), "") && accepts(&words.complement(), "\u{ff}"));
+    //Operations come out minimal, so equivalent constructions agree
+    let via_ops = words.intersect(&bad.complement());
+    assert_eq!(via_ops.state_len(), words.difference(&bad).state_len());
+    assert_eq!(via_ops.state_len(), Dfa::from_regex("[a-z]+&~(.*bad.*)").unwrap().state_len());
+}
+
+#[test]
+fn dfa_comparisons() {
+    let words = Dfa::from_regex("[a-z]+").unwrap();
+    let route = Dfa::from_regex("default|via").unwrap();
+    assert_eq!(route.is_subset_of(&words), Ok(()));
+    //Shortest witnesses, lowest bytes first
+    assert_eq!(words.is_subset_of(&route), Err(b"a".to_vec()));
+    assert_eq!(route.equivalent(&words), Err(b"a".to_vec()));
+    assert_eq!(Dfa::from_regex("a*").unwrap().is_subset_of(&words), Err(Vec::new()));
+    assert_eq!(Dfa::from_regex("ab+").unwrap().equivalent(&Dfa::from_regex("a(b|bb+)").unwrap()), Ok(()));
+    assert_eq!(words.equivalent(&Dfa::from_regex("[a-z]*[a-y]").unwrap()), Err(b"z".to_vec()));
+    assert!(words.matches(b"z") && !route.matches(b"z"));
+}
+
+#[test]
+fn dfa_enumeration() {
+    let dfa = Dfa::from_regex("b|a[ab]*").unwrap();
+    let lines: Vec<Vec<u8>> = dfa.enumerate(3).collect();
+    let expected: Vec<&[u8]> = vec![b"a", b"b", b"aa", b"ab", b"aaa", b"aab", b"aba", b"abb"];
+    assert_eq!(lines, expected);
+    assert_eq!(Dfa::from_regex("a*").unwrap().enumerate(2).collect::<Vec<_>>(), vec![b"".to_vec(), b"a".to_vec(), b"aa".to_vec()]);
+    assert_eq!(Dfa::from_regex("#").unwrap().enumerate(5).count(), 0);
+}
+
+#[test]
+fn dfa_sampling() {
+    let dfa = Dfa::from_regex("[a-z]{2,6}[0-9]?").unwrap();
+    let samples: Vec<Vec<u8>> = dfa.sample(7, 10, Weighting::Uniform).take(200).collect();
+    assert!(samples.iter().all(|line| dfa.matches(line)));
+    //Reproducible from the seed
+    assert_eq!(samples, dfa.sample(7, 10, Weighting::Uniform).take(200).collect::<Vec<_>>());
+    //Uniform sampling all but ignores the short lines, which length weighting doesn't
+    let short = |weighting| dfa.sample(7, 10, weighting).take(200).filter(|line| line.len() <= 3).count();
+    assert!(short(Weighting::Uniform) < 10 && short(Weighting::ByLength) > 40);
+    //The length bound is respected, even when it leaves nothing to sample
+    assert!(dfa.sample(1, 3, Weighting::Uniform).take(50).all(|line| line.len() <= 3));
+    assert_eq!(dfa.sample(1, 1, Weighting::Uniform).next(), None);
+    //Bounds long enough to overflow plain counts still sample
+    assert!(Dfa::from_regex("@").unwrap().sample(3, 400, Weighting::Uniform).take(5).all(|line| line.len() >= 390));
+}
+
+#[test]
+fn dfa_near_misses() {
+    let dfa = Dfa::from_regex("[a-z]+[0-9]").unwrap();
+    let misses: Vec<Vec<u8>> = dfa.near_misses(11, 8, Weighting::ByLength).take(100).collect();
+    assert_eq!(misses.len(), 100);
+    assert!(misses.iter().all(|line| !dfa.matches(line)));
+    //There are none to be had when every line matches
+    assert_eq!(Dfa::from_regex("@").unwrap().near_misses(11, 8, Weighting::Uniform).next(), None);
+}
+
+#[test]
+fn dfa_graphs() {
+    let dfa = Dfa::from_regex("a[^b]*").unwrap();
+    assert_eq!(dfa.to_dot(false), concat!(
+        "digraph dfa {\n    rankdir=LR;\n    node [shape=circle];\n    start [shape=point];\n",
+        "    1;\n    2 [shape=doublecircle];\n",
+        "    0 [label=\"dead\", style=dashed, color=gray, fontcolor=gray];\n",
+        "    start -> 1;\n",
+        "    1 -> 0 [label=\"[^a]\", style=dashed, color=gray, fontcolor=gray];\n",
+        "    1 -> 2 [label=\"a\"];\n",
+        "    2 -> 2 [label=\"[^b]\"];\n",
+        "    2 -> 0 [label=\"b\", style=dashed, color=gray, fontcolor=gray];\n",
+        "}\n",
+    ));
+    assert!(!dfa.to_dot(true).contains("dead") && !dfa.to_mermaid(true).contains("dead"));
+    //Labels escape regex metacharacters and unprintable bytes, and Mermaid's markup characters on top
+    let dfa = Dfa::from_regex("[\\-\"\t<]").unwrap();
+    assert!(dfa.to_dot(true).contains(r#"1 -> 2 [label="[\\t\\\"\\-<]"];"#));
+    assert!(dfa.to_mermaid(true).contains(r#"s1 -->|"[\t\#34;\-#60;]"| s2"#));
+    assert!(Dfa::from_regex("@").unwrap().to_mermaid(false).contains(r#"s1 -->|"."| s1"#));
+    //Labels are regex crate syntax, escapes included
+    let dfa = Dfa::from_regex(&format!("[\t\n\x7f{}]", r#"\\\|&?*+{}~\[\].#@"()^\-"#)).unwrap();
+    let dot = dfa.to_dot(true);
+    let label = dot.split_once("1 -> 2 [label=\"").unwrap().1.split_once("\"];").unwrap().0.replace("\\\"", "\"").replace("\\\\", "\\");
+    assert!(same_lines(&dfa, &DFA::new(&format!("(?-u)^{}$", label)).unwrap()), "{} doesn't match the same bytes", label);
+    assert_eq!(label, r#"[\t\n\"\#\&\(-\+\-\.\?\@\[-\^\{-\x7f]"#);
+}
+
+/// Whether two DFAs accept the same lines, walking them side by side over every byte
+fn same_lines(a: &dyn Automaton, b: &dyn Automaton) -> bool {
+    let start = |dfa: &dyn Automaton| dfa.start_state(&Config::new().anchored(Anchored::Yes)).unwrap();
+    let accepts = |dfa: &dyn Automaton, state| dfa.is_match_state(dfa.next_eoi_state(state));
+    let mut seen = HashSet::from([(start(a), start(b))]);
+    let mut stack = vec![(start(a), start(b))];
+    while let Some((state_a, state_b)) = stack.pop() {
+        if accepts(a, state_a) != accepts(b, state_b) { return false }
+        if a.is_dead_state(state_a) && b.is_dead_state(state_b) { continue }
+        for byte in 0..=255 {
+            let next = (a.next_state(state_a, byte), b.next_state(state_b, byte));
+            if seen.insert(next) { stack.push(next); }
+        }
+    }
+    true
+}
+
+#[test]
+fn to_regex_round_trips() {
+    for (brics, expected) in [
+        ("[a-z]*A(1|[2-9]+)", "[a-z]*A(?:1|[2-9]+)"),
+        ("abc|abd", "ab[cd]"),
+        ("(ab)+", "(?:ab)+"),
+        ("x(ab)+|x", "x(?:ab)*"),
+        ("x?[0-9][0-9][0-9]", "x?[0-9]{3}"),
+        ("()", ""),
+        ("#", "(?-u)[^\\x00-\\xFF]"),
+    ] {
+        assert_eq!(Dfa::from_regex(brics).unwrap().to_regex(), expected);
+    }
+    for brics in ["[a-z]*A(1|[2-9]+)", "(a|b)*abb", "~(.*bad.*)&[a-z]+", "\"a.b\\\\c\"[^\u{0}-\u{7f}]*", "[ -~]{2,4}|(x|yz)*y", "@", "#", "(\"(\"|\"[\")*\"^$&-~#\""] {
+        let dfa = Dfa::from_regex(brics).unwrap();
+        let regex = dfa.to_regex();
+        let round_trip = load_regex(regex.clone()).unwrap_or_else(|e| panic!("{:?} from {:?} doesn't compile: {}", regex, brics, e));
+        assert!(same_lines(&dfa, round_trip.as_ref()), "{:?} doesn't match the same lines as {:?}", regex, brics);
+    }
+    let example_regex = example_dfa().to_regex();
+    assert!(same_lines(&example_dfa(), load_regex(example_regex).unwrap().as_ref()));
+}
+
+//Serialization tests
+#[test]
+fn serialized_format_is_canonical() {
+    let dir = env::temp_dir();
+    let path = |name: &str| dir.join(format!("monitor-{}-{}.bc", name, std::process::id()));
+    //Separately built DFAs iterate their hash maps in different orders, but serialize the same
+    example_dfa().serialize_to(&path("a")).unwrap();
+    example_dfa().serialize_to(&path("b")).unwrap();
+    let bytes = fs::read(path("a")).unwrap();
+    assert_eq!(bytes, fs::read(path("b")).unwrap());
+    assert_eq!(&bytes[..6], b"SDFA\x01\x00");
+    assert!(Dfa::deserialize(path("a")).unwrap().equivalent(&example_dfa()).is_ok());
+    //Unknown versions and corrupt files are turned away
+    let mut newer = bytes.clone();
+    newer[4] = 2;
+    fs::write(path("newer"), newer).unwrap();
+    let err = Dfa::deserialize(path("newer")).err().unwrap().to_string();
+    assert!(err.contains("format version 2 is not supported (this monitor reads version 1)"), "{}", err);
+    let mut corrupt = bytes.clone();
+    *corrupt.last_mut().unwrap() ^= 1;
+    fs::write(path("corrupt"), corrupt).unwrap();
+    assert!(Dfa::deserialize(path("corrupt")).err().unwrap().to_string().contains("content hash doesn't match"));
+    for name in ["a", "b", "newer", "corrupt"] { fs::remove_file(path(name)).unwrap(); }
+    //Files from before the format was versioned still load
+    let legacy = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../json-to-dfa/serialized_example_dfa.bc");
+    assert!(!fs::read(&legacy).unwrap().starts_with(b"SDFA"));
+    assert!(Dfa::deserialize(legacy).unwrap().equivalent(&example_dfa()).is_ok());
+}
+#[test]
+fn pre_versioning_format_decodes() {
+    //Written by the unversioned serializer, which left out the table entry of the sink state after the %
+    let legacy = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../json-to-dfa/serialized_percentage_dfa.bc");
+    let dfa = Dfa::deserialize(legacy).unwrap();
+    assert!(dfa.equivalent(&Dfa::from_regex(r"[0-9]+(\.[0-9]+)?%").unwrap()).is_ok());
+    assert!(dfa.matches(b"12.5%") && !dfa.matches(b"12.5%%"));
+}
+
+#[test]
+fn flat_format_maps_in_place() {
+    let path = |name: &str| env::temp_dir().join(format!("monitor-flat-{}-{}.bc", name, std::process::id()));
+    let dfa = Dfa::from_regex("[a-z]*A(1|[2-9]+)").unwrap();
+    dfa.serialize_flat_to(&path("ok")).unwrap();
+    let bytes = fs::read(path("ok")).unwrap();
+    assert_eq!(&bytes[..6], b"SDFM\x01\x00");
+    //SAFETY: nothing changes the file while it's mapped
+    let mapped = unsafe { Dfa::deserialize_mapped(path("ok")) }.unwrap();
+    assert!(mapped.equivalent(&dfa).is_ok());
+    assert_eq!((mapped.state_len(), mapped.alphabet_len()), (dfa.state_len(), dfa.alphabet_len()));
+    //The sparse form is rebuilt on demand, numbering states by row
+    assert_eq!(mapped.validate(), Ok(()));
+    assert_eq!(mapped.to_regex(), dfa.to_regex());
+    assert_eq!(mapped.dense_id(StateID::must(1)), mapped.start_state(&Config::new()).ok());
+    //Unless asked to map it, the table is copied, so changes to the file after loading don't reach it
+    fs::write(path("copied"), &bytes).unwrap();
+    let copied = Dfa::deserialize(path("copied")).unwrap();
+    fs::write(path("copied"), vec![0xff; bytes.len()]).unwrap();
+    assert!(copied.equivalent(&dfa).is_ok() && copied.matches(b"abA1"));
+    fs::remove_file(path("copied")).unwrap();
+    //Corrupt tables are turned away before they're used
+    let table_at = 8 + 12 + 256;
+    let mut reasons = Vec::new();
+    for (name, edit) in [
+        ("dangling", Box::new(|bytes: &mut Vec<u8>| bytes[table_at + 4 * 9] = 0xff) as Box<dyn Fn(&mut Vec<u8>)>),
+        ("misaligned", Box::new(|bytes: &mut Vec<u8>| bytes[table_at + 4 * 9] += 1)),
+        ("dead", Box::new(|bytes: &mut Vec<u8>| bytes[table_at] = bytes[16])),
+        ("truncated", Box::new(|bytes: &mut Vec<u8>| { bytes.pop(); })),
+        ("version", Box::new(|bytes: &mut Vec<u8>| bytes[4] = 9)),
+        //A row nothing leads to - a sound table, but not a valid DFA
+        ("unreachable", Box::new(|bytes: &mut Vec<u8>| {
+            let (stride, rows) = (1 << bytes[8], bytes[12] as usize);
+            let table_end = table_at + 4 * (rows * stride);
+            bytes.splice(table_end..table_end, vec![0; 4 * stride]);
+            bytes.push(0);
+            bytes[12] += 1;
+        })),
+    ] {
+        let mut corrupt = bytes.clone();
+        edit(&mut corrupt);
+        fs::write(path(name), corrupt).unwrap();
+        let copied = Dfa::deserialize(path(name)).err().unwrap().to_string();
+        //SAFETY: nothing changes the file while it's mapped
+        assert_eq!(unsafe { Dfa::deserialize_mapped(path(name)) }.err().unwrap().to_string(), copied);
+        reasons.push(copied);
+        fs::remove_file(path(name)).unwrap();
+    }
+    fs::remove_file(path("ok")).unwrap();
+    for (reason, expected) in reasons.iter().zip([
+        "transition to a state that doesn't exist", "transition to a state that doesn't exist", "transitions leading out of the dead state",
+        "length doesn't match its row count", "layout version 9 is not supported", "is unreachable from the start state",
+    ]) {
+        assert!(reason.contains(expected), "{:?} doesn't say {:?}", reason, expected);
+    }
+}
+
+#[test]
+fn cache_management() {
+    let dir = env::temp_dir().join(format!("monitor-cache-{}", std::process::id()));
+    let cache = Cache::at(dir.clone());
+    assert!(cache.entries().unwrap().is_empty());
+    let letters = Dfa::from_regex("[a-z]+").unwrap().serialize_into(&cache, Some("[a-z]+")).unwrap();
+    //The same DFA lands on the same entry
+    assert_eq!(Dfa::from_regex("[a-z][a-z]*").unwrap().serialize_into(&cache, None).unwrap(), letters);
+    let digits = Dfa::from_regex("[0-9]+").unwrap().serialize_into(&cache, None).unwrap();
+    fs::write(dir.join("notes.txt"), "not a DFA").unwrap();
+    let day = std::time::Duration::from_secs(24 * 60 * 60);
+    File::options().write(true).open(&letters).unwrap().set_modified(SystemTime::now() - 2 * day).unwrap();
+    let entries = cache.entries().unwrap();
+    assert_eq!(entries.iter().map(|entry| (&entry.path, entry.source.as_deref())).collect::<Vec<_>>(), [(&letters, Some("[a-z]+")), (&digits, None)]);
+    assert!(entries.iter().all(|entry| entry.verify().is_ok()));
+    //Entries that don't hold what their name says fail verification
+    let renamed = dir.join("sdfa-00000000.bc");
+    fs::copy(&digits, &renamed).unwrap();
+    let mut corrupt = fs::read(&digits).unwrap();
+    *corrupt.last_mut().unwrap() ^= 1;
+    let corrupted = dir.join("sdfa-11111111.bc");
+    fs::write(&corrupted, corrupt).unwrap();
+    let verdicts: Vec<(PathBuf, Result<(), String>)> = cache.entries().unwrap().into_iter().map(|entry| (entry.path.clone(), entry.verify())).collect();
+    assert!(verdicts.iter().any(|(path, verdict)| *path == renamed && verdict.as_ref().unwrap_err().contains("different DFA than its name says")));
+    assert!(verdicts.iter().any(|(path, verdict)| *path == corrupted && verdict.as_ref().unwrap_err().contains("content hash doesn't match")));
+    fs::remove_file(renamed).unwrap();
+    fs::remove_file(corrupted).unwrap();
+    //Pruning by age takes the source file along with the DFA
+    let removed = cache.gc(Some(day), None).unwrap();
+    assert_eq!(removed.iter().map(|entry| &entry.path).collect::<Vec<_>>(), [&letters]);
+    assert!(!letters.with_extension("source").exists());
+    assert_eq!(cache.gc(Some(day), Some(u64::MAX)).unwrap().len(), 0);
+    cache.clear().unwrap();
+    assert!(cache.entries().unwrap().is_empty());
+    assert!(dir.join("notes.txt").exists());
+    fs::remove_dir_all(dir).unwrap();
+    //Command line ages and sizes
+    assert_eq!(parse_age("30d"), Ok(30 * day));
+    assert_eq!(parse_age("90m"), Ok(std::time::Duration::from_secs(90 * 60)));
+    assert!(parse_age("30").is_err() && parse_age("d").is_err());
+    assert_eq!(parse_size("100M"), Ok(100 << 20));
+    assert_eq!(parse_size("512"), Ok(512));
+    assert!(parse_size("lots").is_err());
+}
+
+//Wrapped command tests
+fn typed(spec: &str, on_fail: FailPolicy) -> Option<StreamType> {
+    Some(StreamType { spec: spec.to_string(), dfa: load_type(spec).unwrap(), on_fail })
+}
+fn run_sh(script: &str, types: StreamTypes, stop: Stop) -> Result<i32, MonitorError> {
+    run_wrapped(&["sh", "-c", script].map(String::from), types, Framing::default(), stop)
+}
+#[test]
+fn run_wrapped_command() {
+    let stop = Stop { signal: Signal::SIGTERM, grace: std::time::Duration::from_secs(5), process_group: false };
+    let stdout = |spec: &str, on_fail| StreamTypes { stdout: typed(spec, on_fail), ..StreamTypes::default() };
+    assert_eq!(run_sh("echo a; echo b", stdout("[a-z]+", FailPolicy::Stop), stop).unwrap(), 0);
+    //The command's own failure is passed on when its output validated
+    assert_eq!(run_sh("echo a; exit 3", stdout("[a-z]+", FailPolicy::Stop), stop).unwrap(), 3);
+    //A violation stops the command rather than waiting for it
+    let started = std::time::Instant::now();
+    assert_eq!(run_sh("echo a; echo 1; exec sleep 10", stdout("[a-z]+", FailPolicy::Stop), stop).unwrap(), MonitorError::VALIDATION_EXIT_CODE);
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    //Commands ignoring the signal are killed once the grace period is up, and a process group is signalled as a whole
+    let stubborn = Stop { grace: std::time::Duration::from_millis(100), process_group: true, ..stop };
+    let started = std::time::Instant::now();
+    assert_eq!(run_sh("trap '' TERM; echo 1; sleep 10; sleep 10", stdout("[a-z]+", FailPolicy::Stop), stubborn).unwrap(), MonitorError::VALIDATION_EXIT_CODE);
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    //Other policies let the command run to its end, but still fail the run
+    assert_eq!(run_sh("echo a; echo 1; echo b", stdout("[a-z]+", FailPolicy::Drop), stop).unwrap(), MonitorError::VALIDATION_EXIT_CODE);
+    let stderr = StreamTypes { stderr: typed("warn: .*", FailPolicy::Close), ..StreamTypes::default() };
+    assert_eq!(run_sh("echo 'warn: a' >&2; echo oops >&2; exit 3", stderr, stop).unwrap(), MonitorError::VALIDATION_EXIT_CODE);
+    let both = StreamTypes { stdout: typed("[a-z]+", FailPolicy::Stop), stderr: typed("warn: .*", FailPolicy::Stop), ..StreamTypes::default() };
+    assert_eq!(run_sh("echo a; echo 'warn: a' >&2; exit 2", both, stop).unwrap(), 2);
+    let missing = run_wrapped(&[String::from("/nonexistent")], stdout("a", FailPolicy::Stop), Framing::default(), stop);
+    assert!(matches!(missing, Err(MonitorError::Io(_))));
+    //Command line signals and grace periods
+    assert_eq!(parse_signal("TERM"), Ok(Signal::SIGTERM));
+    assert_eq!(parse_signal("sigint"), Ok(Signal::SIGINT));
+    assert_eq!(parse_signal("9"), Ok(Signal::SIGKILL));
+    assert!(parse_signal("FOO").is_err() && parse_signal("999").is_err());
+    assert_eq!(parse_grace("0.5"), Ok(std::time::Duration::from_millis(500)));
+    assert!(parse_grace("-1").is_err() && parse_grace("soon").is_err());
+}
+#[test]
+fn interposed_stream_policies() {
+    let input = b"1\n2\nx\n3\n";
+    let pass = |on_fail| {
+        let (events, received) = std::sync::mpsc::channel();
+        let mut sink = Vec::new();
+        interpose(Pipe::Stdin, &input[..], &mut sink, &typed("[0-9]+", on_fail).unwrap(), Framing::default(), &events).unwrap();
+        drop(events);
+        let violations: Vec<(Pipe, usize)> = received.iter().map(|event| match event {
+            Event::Violation(pipe, line_no, _) => (pipe, line_no),
+            Event::Closed(..) => panic!("Interposers only report closing when spawned"),
+        }).collect();
+        (String::from_utf8(sink).unwrap(), violations)
+    };
+    assert_eq!(pass(FailPolicy::Stop), (String::from("1\n2\n"), vec![(Pipe::Stdin, 3)]));
+    assert_eq!(pass(FailPolicy::Drop), (String::from("1\n2\n3\n"), vec![(Pipe::Stdin, 3)]));
+    assert_eq!(pass(FailPolicy::Close), (String::from("1\n2\n"), vec![(Pipe::Stdin, 3)]));
+}
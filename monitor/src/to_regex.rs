@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use crate::{byte_dfa::ByteDfa, Dfa};
+
+/// Regular expression built up during state elimination, kept simplified as it goes by its constructors
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Re {
+    Nothing,
+    Empty,
+    Bytes(Box<[bool; 256]>),
+    Concat(Vec<Re>),
+    Alt(Vec<Re>),
+    Star(Box<Re>),
+}
+impl Re {
+    fn concat(a: Re, b: Re) -> Re {
+        match (a, b) {
+            (Re::Nothing, _) | (_, Re::Nothing) => Re::Nothing,
+            (Re::Empty, re) | (re, Re::Empty) => re,
+            (a, b) => {
+                let mut items = a.into_items();
+                items.extend(b.into_items());
+                Re::Concat(items)
+            },
+        }
+    }
+    fn into_items(self) -> Vec<Re> { if let Re::Concat(items) = self { items } else { vec![self] } }
+    /// Alternation, merging byte classes into one, dropping duplicates and factoring out a common prefix or suffix
+    /// when there are only two alternatives
+    fn alt(a: Re, b: Re) -> Re {
+        let (a, b) = match (a, b) {
+            (Re::Nothing, re) | (re, Re::Nothing) => return re,
+            (a, b) if a == b => return a,
+            //X* is the shorter way of writing (X+)?
+            (Re::Empty, re) | (re, Re::Empty) if matches!(re, Re::Star(_)) => return re,
+            (Re::Empty, re) | (re, Re::Empty) if re.plus_of().is_some() => return Re::Star(Box::new(re.plus_of().unwrap())),
+            (Re::Bytes(a), Re::Bytes(b)) => return Re::Bytes(Box::new(std::array::from_fn(|byte| a[byte] || b[byte]))),
+            (Re::Alt(mut alts), re) | (re, Re::Alt(mut alts)) => {
+                for re in if let Re::Alt(more) = re { more } else { vec![re] } { Re::push_alt(&mut alts, re); }
+                return if alts.len() == 1 { alts.pop().unwrap() } else { Re::Alt(alts) }
+            },
+            pair => pair,
+        };
+        let (mut a, mut b) = (a.into_items(), b.into_items());
+        let prefix = a.iter().zip(&b).take_while(|(a, b)| a == b).count();
+        let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+        if prefix == 0 && suffix == 0 {
+            let mut alts = Vec::new();
+            Re::push_alt(&mut alts, Re::from_items(a));
+            Re::push_alt(&mut alts, Re::from_items(b));
+            return Re::Alt(alts)
+        }
+        let tail = a.split_off(a.len() - suffix);
+        b.truncate(b.len() - suffix);
+        let middle = Re::alt(Re::from_items(a.split_off(prefix)), Re::from_items(b.split_off(prefix)));
+        [Re::from_items(a), middle, Re::from_items(tail)].into_iter().fold(Re::Empty, Re::concat)
+    }
+    /// X, if this is X followed by X*
+    fn plus_of(&self) -> Option<Re> {
+        let Re::Concat(items) = self else { return None };
+        let (Some(Re::Star(starred)), init) = (items.last(), &items[..items.len() - 1]) else { return None };
+        (**starred == Re::from_items(init.to_vec())).then(|| (**starred).clone())
+    }
+    fn push_alt(alts: &mut Vec<Re>, re: Re) {
+        if alts.contains(&re) { return }
+        if let Re::Bytes(bytes) = &re && let Some(Re::Bytes(class)) = alts.iter_mut().find(|alt| matches!(alt, Re::Bytes(_))) {
+            for byte in 0..256 { class[byte] |= bytes[byte]; }
+            return
+        }
+        alts.push(re);
+    }
+    fn from_items(mut items: Vec<Re>) -> Re {
+        match items.len() {
+            0 => Re::Empty,
+            1 => items.pop().unwrap(),
+            _ => Re::Concat(items),
+        }
+    }
+    fn star(re: Re) -> Re {
+        match re {
+            Re::Nothing | Re::Empty => Re::Empty,
+            Re::Star(_) => re,
+            Re::Alt(alts) if alts.contains(&Re::Empty) => {
+                Re::star(alts.into_iter().filter(|alt| *alt != Re::Empty).fold(Re::Nothing, Re::alt))
+            },
+            re => Re::Star(Box::new(re)),
+        }
+    }
+
+    /// Whether rendering needs Unicode mode off (which `[^\x00-\xFF]`, standing in for nothing, does too)
+    fn has_non_ascii(&self) -> bool {
+        match self {
+            Re::Nothing => true,
+            Re::Empty => false,
+            Re::Bytes(bytes) => bytes[0x80..].iter().any(|byte| *byte),
+            Re::Concat(res) | Re::Alt(res) => res.iter().any(Re::has_non_ascii),
+            Re::Star(re) => re.has_non_ascii(),
+        }
+    }
+    /// Renders in regex_automata syntax, parenthesizing anything binding looser than `prec` (0: alternation,
+    /// 1: concatenation, 2: repetition)
+    fn render(&self, prec: u8, out: &mut String) {
+        let group = |out: &mut String, inner: &dyn Fn(&mut String), needed: bool| {
+            if needed { out.push_str("(?:"); }
+            inner(out);
+            if needed { out.push(')'); }
+        };
+        match self {
+            Re::Nothing => out.push_str("[^\\x00-\\xFF]"),
+            Re::Empty => group(out, &|_| {}, prec == 2),
+            Re::Bytes(bytes) => out.push_str(&byte_class(bytes)),
+            Re::Star(re) => { re.render(2, out); out.push('*'); },
+            Re::Concat(items) => group(out, &|out| render_items(items, out), prec == 2),
+            Re::Alt(alts) => {
+                let optional = alts.contains(&Re::Empty);
+                let alts: Vec<&Re> = alts.iter().filter(|alt| **alt != Re::Empty).collect();
+                let inner = |out: &mut String| for (i, alt) in alts.iter().enumerate() {
+                    if i > 0 { out.push('|'); }
+                    alt.render(if alts.len() == 1 { 2 } else { 1 }, out);
+                };
+                group(out, &inner, if optional { alts.len() > 1 } else { prec > 0 });
+                if optional { out.push('?'); }
+            },
+        }
+    }
+}
+
+/// Renders a concatenation, writing `X X*` as `X+` and runs of the same item as counted repetitions
+fn render_items(items: &[Re], out: &mut String) {
+    let mut i = 0;
+    while i < items.len() {
+        let item = &items[i];
+        let plus = (i + 1..items.len()).find(|star| matches!(&items[*star], Re::Star(starred) if **starred == Re::from_items(items[i..*star].to_vec())));
+        if let Some(star) = plus {
+            Re::from_items(items[i..star].to_vec()).render(2, out);
+            out.push('+');
+            i = star + 1;
+            continue
+        }
+        let run = items[i..].iter().take_while(|next| *next == item).count();
+        let single_byte = matches!(item, Re::Bytes(bytes) if bytes.iter().filter(|byte| **byte).count() == 1);
+        if run >= 2 && (run >= 4 || !single_byte) {
+            item.render(2, out);
+            out.push_str(&format!("{{{}}}", run));
+            i += run;
+        } else {
+            item.render(1, out);
+            i += 1;
+        }
+    }
+}
+
+/// A set of bytes as a literal or class, negated if the class is shorter that way (which only happens for classes
+/// with bytes above 0x7f, so with Unicode mode off)
+fn byte_class(bytes: &[bool; 256]) -> String {
+    let count = bytes.iter().filter(|byte| **byte).count();
+    if count == 1 { return escape(bytes.iter().position(|byte| *byte).unwrap() as u8) }
+    let ranges = |set: bool| {
+        let mut ranges = String::new();
+        let mut byte = 0;
+        while byte < 256 {
+            if bytes[byte] != set { byte += 1; continue }
+            let start = byte;
+            while byte < 256 && bytes[byte] == set { byte += 1; }
+            ranges += &escape(start as u8);
+            if byte - start > 2 { ranges.push('-'); }
+            if byte - start > 1 { ranges += &escape((byte - 1) as u8); }
+        }
+        ranges
+    };
+    let (positive, negative) = (ranges(true), ranges(false));
+    if count == 256 { "[\\x00-\\xFF]".to_string() }
+    else if count > 128 && negative.len() < positive.len() { format!("[^{}]", negative) }
+    else { format!("[{}]", positive) }
+}
+
+fn escape(byte: u8) -> String {
+    match byte {
+        b'\t' => "\\t".to_string(),
+        b'\n' => "\\n".to_string(),
+        b'\r' => "\\r".to_string(),
+        b'\\' | b'.' | b'+' | b'*' | b'?' | b'(' | b')' | b'|' | b'[' | b']' | b'{' | b'}' | b'^' | b'$' | b'#' | b'&' | b'-' | b'~' => format!("\\{}", byte as char),
+        0x20..=0x7e => (byte as char).to_string(),
+        _ => format!("\\x{:02X}", byte),
+    }
+}
+
+/// Regular expression matching exactly the lines the DFA does, by state elimination over the minimized DFA
+pub(crate) fn to_regex(dfa: &Dfa) -> String {
+    let dfa = ByteDfa::from_dfa(dfa).minimize();
+    //The generalized NFA's own start and final states come after the DFA's (its dead state, 0, is left out)
+    let (start, end) = (dfa.len(), dfa.len() + 1);
+    let mut edges: BTreeMap<(usize, usize), Re> = BTreeMap::new();
+    if dfa.start != 0 { add_edge(&mut edges, start, dfa.start, Re::Empty); }
+    for state in 1..dfa.len() {
+        if dfa.accept[state] { add_edge(&mut edges, state, end, Re::Empty); }
+        for next in 1..dfa.len() {
+            if dfa.next[state].contains(&next) {
+                add_edge(&mut edges, state, next, Re::Bytes(Box::new(dfa.next[state].map(|target| target == next))));
+            }
+        }
+    }
+    let mut remaining: Vec<usize> = (1..dfa.len()).collect();
+    while !remaining.is_empty() {
+        //Eliminating the states with the fewest paths through them first keeps the regex small
+        let paths = |state: usize| {
+            let ins = edges.keys().filter(|(from, to)| *to == state && *from != state).count();
+            let outs = edges.keys().filter(|(from, to)| *from == state && *to != state).count();
+            ins * outs
+        };
+        let position = (0..remaining.len()).min_by_key(|i| (paths(remaining[*i]), remaining[*i])).unwrap();
+        let state = remaining.remove(position);
+        let looped = edges.remove(&(state, state)).map_or(Re::Empty, Re::star);
+        let ins: Vec<(usize, Re)> = edges.iter().filter(|((_, to), _)| *to == state).map(|((from, _), re)| (*from, re.clone())).collect();
+        let outs: Vec<(usize, Re)> = edges.iter().filter(|((from, _), _)| *from == state).map(|((_, to), re)| (*to, re.clone())).collect();
+        edges.retain(|(from, to), _| *from != state && *to != state);
+        for (from, into) in &ins {
+            for (to, out) in &outs {
+                let path = Re::concat(Re::concat(into.clone(), looped.clone()), out.clone());
+                add_edge(&mut edges, *from, *to, path);
+            }
+        }
+    }
+    let re = edges.remove(&(start, end)).unwrap_or(Re::Nothing);
+    //Classes outside ASCII stand for raw bytes only with Unicode mode off
+    let mut regex = String::from(if re.has_non_ascii() { "(?-u)" } else { "" });
+    re.render(0, &mut regex);
+    regex
+}
+
+fn add_edge(edges: &mut BTreeMap<(usize, usize), Re>, from: usize, to: usize, re: Re) {
+    let existing = edges.remove(&(from, to)).unwrap_or(Re::Nothing);
+    edges.insert((from, to), Re::alt(existing, re));
+}
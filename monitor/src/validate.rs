@@ -0,0 +1,41 @@
+use std::collections::{BTreeSet, HashSet};
+use regex_automata::util::primitives::StateID;
+use crate::{DfaDefect, TransitionDesc, TransitionTable};
+
+/// Every structural defect in a sparse DFA description (start and match state defects first, then each state's own
+/// in ascending order of ID). States are known if they have a transition table entry (possibly empty) - the dead
+/// state's entry, if any, must be the lone self loop `Dfa::new` gives it.
+pub(crate) fn defects(start_state: StateID, match_states: &HashSet<StateID>, transition_table: &TransitionTable, dead_state: StateID) -> Vec<DfaDefect> {
+    let mut defects = Vec::new();
+    let known = |sid: &StateID| transition_table.contains_key(sid);
+    if start_state == dead_state { defects.push(DfaDefect::DeadStateMisuse(dead_state, "is the start state")); }
+    else if !known(&start_state) { defects.push(DfaDefect::UnknownStartState(start_state)); }
+    let match_states: BTreeSet<StateID> = match_states.iter().copied().collect();
+    if match_states.contains(&dead_state) { defects.push(DfaDefect::DeadStateMisuse(dead_state, "is a match state")); }
+    defects.extend(match_states.iter().filter(|sid| **sid != dead_state && !known(sid)).map(|sid| DfaDefect::UnknownMatchState(*sid)));
+    if let Some(trans_descs) = transition_table.get(&dead_state) && trans_descs != &[TransitionDesc::Range(u8::MIN, u8::MAX, dead_state)] {
+        defects.push(DfaDefect::DeadStateMisuse(dead_state, "has transitions leading out of it"));
+    }
+    let states: BTreeSet<StateID> = transition_table.keys().copied().filter(|sid| *sid != dead_state).collect();
+    for state in &states {
+        let trans_descs = &transition_table[state];
+        if trans_descs.windows(2).any(|pair| pair[0].range().0 > pair[1].range().0) { defects.push(DfaDefect::UnsortedRanges(*state)); }
+        let mut ranges: Vec<(u8, u8)> = trans_descs.iter().map(TransitionDesc::range).collect();
+        ranges.sort_unstable();
+        defects.extend(ranges.windows(2).filter(|pair| pair[1].0 <= pair[0].1).map(|pair| DfaDefect::OverlappingRanges(*state, pair[0], pair[1])));
+        let targets: BTreeSet<StateID> = trans_descs.iter().map(TransitionDesc::next_state).collect();
+        defects.extend(targets.into_iter().filter(|sid| *sid != dead_state && !known(sid)).map(|sid| DfaDefect::DanglingTarget(*state, sid)));
+    }
+    //Reachability only makes sense from a usable start state
+    if known(&start_state) && start_state != dead_state {
+        let mut reached = HashSet::from([start_state]);
+        let mut stack = vec![start_state];
+        while let Some(state) = stack.pop() {
+            for next in transition_table.get(&state).into_iter().flatten().map(TransitionDesc::next_state) {
+                if reached.insert(next) { stack.push(next); }
+            }
+        }
+        defects.extend(states.iter().filter(|sid| !reached.contains(sid)).map(|sid| DfaDefect::UnreachableState(*sid)));
+    }
+    defects
+}
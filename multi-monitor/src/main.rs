@@ -30,10 +30,10 @@ struct Args {
 fn main() {
     let args = Args::parse();
     //Check to make sure only one kind of operating mode is specified
-    let num_args_specified = vec![&args.regex, &args.grep, &args.ripgrep].iter().fold(
+    let num_args_specified = [&args.regex, &args.grep, &args.ripgrep].iter().fold(
         0, 
-        |accum, el| if let Some(_) = el { accum + 1 } else { accum }
-    ) + (if let Some(_) = args.dfa_path { 1 } else { 0 });
+        |accum, el| if el.is_some() { accum + 1 } else { accum }
+    ) + (if args.dfa_path.is_some() { 1 } else { 0 });
     assert_eq!(num_args_specified, 1, "Must have one (and only one) operating mode specified");
     //Execute proper functionality
     let mon_binary = proj_root().join(MON_BINARY);
@@ -62,6 +62,7 @@ fn main() {
     }
 }
 
+#[allow(clippy::zombie_processes)] //grep is left to exit on its own once its input closes
 fn _nonstreaming_grep_monitor(grep_cmd: &str, options: &str, regex: String) {
     //Run grep command
     let mut grep_cmd = Command::new(grep_cmd)
@@ -106,6 +107,7 @@ fn _nonstreaming_grep_monitor(grep_cmd: &str, options: &str, regex: String) {
     }
 }
 
+#[allow(clippy::zombie_processes)] //grep is left to exit on its own once its input closes
 fn streaming_grep_mon(grep_cmd: &str, options: &str, regex: String) {
     //Run grep command
     let mut grep_cmd = Command::new(grep_cmd)
@@ -120,12 +122,10 @@ fn streaming_grep_mon(grep_cmd: &str, options: &str, regex: String) {
     thread::spawn(move || {
         let stdin_reader = io::stdin().lock();
         //let mut stdout_writer = io::stdout().lock(); - For no overhead streaming implementation
-        let mut counter: u32 = 1;
-        for line_res in stdin_reader.lines() {
+        for (counter, line_res) in (1u32..).zip(stdin_reader.lines()) {
             let line = line_res.expect("Error reading line of stdin");
             writeln!(child_stdin, "{}", line).expect("Failed to write to child stdin"); //Write line to child
-            writer.write(&counter.to_le_bytes()).expect("Failed to write line number to parent"); //Write line number to parent
-            counter += 1;
+            writer.write_all(&counter.to_le_bytes()).expect("Failed to write line number to parent"); //Write line number to parent
             //writeln!(stdout_writer, "{}", line).expect("Failed to write line to stdout"); - For no overhead streaming implementation
         }
     });
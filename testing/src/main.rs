@@ -1,24 +1,24 @@
 use std::{error, result};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use std::process::{Command, ExitStatus, Stdio, exit};
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::io::{Result, Error, ErrorKind};
-use std::fs::{create_dir, File};
+use std::io::{Result, Error};
+use std::fs::File;
 use std::env::consts::OS;
 use clap::Parser;
 use csv::{ReaderBuilder, Writer};
-use monitor::Dfa;
+use regex_automata::{dfa::Automaton, HalfMatch, Input};
+use monitor::{Cache, Dfa};
 
 const MON_BINARY: &str = "../target/release/monitor";
 const MULTIMON_BINARY: &str = "../target/release/multi-monitor";
-const DFA_BUILDER_JAR: &str = "dfa-builder.jar";
-const DFA_CACHE: &str = "dfa-cache";
 const BENCHMARKS_CSV: &str = "benchmarks-and-results/benchmarks2.csv";
 const RESULTS_CSV: &str = "benchmarks-and-results/benchmark_results2.csv";
 const COMP_RESULTS_CSV: &str = "benchmarks-and-results/comp_benchmark_results2.csv";
+const TABLE_RESULTS_CSV: &str = "benchmarks-and-results/table_benchmark_results2.csv";
+const STARTUP_RESULTS_CSV: &str = "benchmarks-and-results/startup_benchmark_results2.csv";
 const TRIALS: u8 = 10;
-const BAD_CHARS: [char; 2] = ['<','>'];
 static INSTANCE_COUNTER: AtomicU32 = AtomicU32::new(1);
 
 #[derive(Debug)]
@@ -74,7 +74,7 @@ impl BenchMark { //Core functionality for speed benchmarking w/ DFAs
         let full_res = self.handle_test_res(test_res, "Running command raw failed")?;
         Some(full_res.1)
     }
-    fn time_mon(&self, dfa_path: &PathBuf) -> Option<(ExitStatus, Duration)> { 
+    fn time_mon(&self, dfa_path: &Path) -> Option<(ExitStatus, Duration)> { 
         let test_res = Self::time_exec(format!(
             "{} | {} -d {}", 
             self.cmd, 
@@ -83,39 +83,14 @@ impl BenchMark { //Core functionality for speed benchmarking w/ DFAs
         self.handle_test_res(test_res, "Running command with monitor failed")
     }
     fn make_dfa(&self) -> Result<PathBuf> {
-        //Build JSON DFA representation out of type (regular expression)
-        let escaped_regex = Self::escape_bad_chars(&self.typ);
-        let builder_out = Command::new("java")
-            .args(["-jar", self.proj_root.join(DFA_BUILDER_JAR).to_str().unwrap(), escaped_regex.as_str()])
-            .current_dir(self.proj_root.join(DFA_CACHE))
-            .output()?;
-        if !builder_out.status.success() || !builder_out.stderr.is_empty() { return Err(Error::new(
-            ErrorKind::InvalidData, 
-            format!("DFA builder errored\nTo debug, try running java -jar dfa-builder.jar \"{}\"", escaped_regex)
-        )) }
-        let json_dfa_path = self.proj_root.join(DFA_CACHE).join(PathBuf::from(String::from_utf8_lossy(&builder_out.stdout).trim()));
-        //Ok(json_dfa_path)
-        //Deserialize into Dfa from JSON, then serialize into binary form (for quicker deserialization)
-        Ok(Dfa::deserialize_from_json(json_dfa_path).serialize())
-    }
-    fn escape_bad_chars(regex: &String) -> String {
-        let mut escaped = String::new();
-        //escaped.push('^');
-        for c in regex.clone().chars() {
-            if BAD_CHARS.contains(&c) {
-                escaped.push('\\'); // Escape with backslash
-            }
-            escaped.push(c);
-        }
-        //escaped.push('$');
-        //println!("{}",escaped);
-        escaped
+        //Compile the type (regular expression) into a DFA, then serialize it into binary form (for quicker deserialization)
+        Dfa::from_regex(&self.typ).map_err(Error::other)?.serialize_into(&dfa_cache(), Some(&self.typ))
     }
     fn handle_test_res<T>(&self, test_res: Result<T>, msg: &str) -> Option<T> {
         match test_res {
             Ok(res) => Some(res),
             Err(e) => {
-                eprintln!("\nBenchmark {} failed:\n{}:\n{}\n", self.num, msg, e.to_string());
+                eprintln!("\nBenchmark {} failed:\n{}:\n{}\n", self.num, msg, e);
                 None
             }
         }
@@ -188,10 +163,117 @@ impl BenchMark { //Functionality for implementation comparative benchmarking
     }
 }
 
+#[derive(Debug)]
+struct TableBenchRes {
+    pub num: u32,
+    pub cmd: String,
+    pub typ: String,
+    pub states: usize,
+    pub classes: usize,
+    pub sparse_time: Duration,
+    pub dense_time: Duration,
+}
+
+impl BenchMark { //Functionality for benchmarking the sparse (linear scan) transition table against the dense one
+    fn time_search<A: Automaton>(dfa: &A, output: &[u8]) -> (bool, Duration) {
+        let timer = Instant::now();
+        let valid = match output.strip_suffix(b"\n") {
+            None if output.is_empty() => dfa.has_empty(),
+            stripped => stripped.unwrap_or(output).split(|b| *b == b'\n').all(|line| {
+                matches!(dfa.try_search_fwd(&Input::new(line)), Ok(Some(mtch)) if mtch == HalfMatch::must(0, line.len()))
+            }),
+        };
+        (valid, timer.elapsed())
+    }
+    fn table_bench(&self) -> Option<TableBenchRes> {
+        let dfa_path = self.handle_test_res(self.make_dfa(), "DFA creation failed")?;
+        let dfa = self.handle_test_res(Dfa::deserialize(dfa_path).map_err(Error::other), "DFA loading failed")?;
+        //Capture the command's output once so that only the search itself is timed
+        let output = self.handle_test_res(
+            Command::new("sh").arg("-c").arg(&self.cmd).stderr(Stdio::null()).output(), 
+            "Running command raw failed"
+        )?.stdout;
+        let mut sparse_times = Vec::new();
+        let mut dense_times = Vec::new();
+        for _ in 0..TRIALS {
+            let (sparse_valid, sparse_time) = Self::time_search(&dfa.as_sparse(), &output);
+            let (dense_valid, dense_time) = Self::time_search(&dfa, &output);
+            if !sparse_valid || !dense_valid {
+                eprintln!("\nBenchmark {} Failed! (Sparse valid: {}, Dense valid: {})\nCommand: {}\nType: {}", self.num, sparse_valid, dense_valid, self.cmd, self.typ);
+                return None
+            }
+            sparse_times.push(sparse_time);
+            dense_times.push(dense_time);
+        }
+        Some(TableBenchRes {
+            num: self.num, cmd: self.cmd.clone(), typ: self.typ.clone(),
+            states: dfa.state_len(), classes: dfa.alphabet_len(),
+            sparse_time: sparse_times.iter().sum::<Duration>() / TRIALS as u32,
+            dense_time: dense_times.iter().sum::<Duration>() / TRIALS as u32,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct StartupBenchRes {
+    pub num: u32,
+    pub typ: String,
+    pub states: usize,
+    pub load_times: (Duration, Duration), //Dfa::deserialize_mapped for the canonical and flat formats
+    pub startup_times: (Duration, Duration), //The monitor over empty input for the canonical and flat formats
+}
+
+impl BenchMark { //Functionality for benchmarking how quickly each serialized DFA format loads
+    fn time_load(path: &Path) -> Result<(usize, Duration)> {
+        let timer = Instant::now();
+        //SAFETY: the benchmarks write their DFAs themselves and leave them alone after
+        let dfa = unsafe { Dfa::deserialize_mapped(path.to_path_buf()) }.map_err(Error::other)?;
+        let elapsed = timer.elapsed();
+        Ok((dfa.state_len(), elapsed))
+    }
+    fn time_startup(&self, path: &Path) -> Option<Duration> {
+        let test_res = Self::time_exec(format!("{} -d {} --mmap < /dev/null 2> /dev/null", self.proj_root.join(MON_BINARY).to_str().unwrap(), path.to_str().unwrap()));
+        let (stat, dur) = self.handle_test_res(test_res, "Running the monitor on empty input failed")?;
+        //Types that don't match an empty line fail validation, which is fine - the DFA still had to be loaded
+        if !matches!(stat.code(), Some(0 | 1)) {
+            eprintln!("\nBenchmark {} Failed! (See above ^^^)\nType: {}", self.num, self.typ);
+            return None
+        }
+        Some(dur)
+    }
+    fn startup_bench(&self) -> Option<StartupBenchRes> {
+        let canonical_path = self.handle_test_res(self.make_dfa(), "DFA creation failed")?;
+        let flat_path = canonical_path.with_extension("flat.bc");
+        let dfa = self.handle_test_res(Dfa::deserialize(canonical_path.clone()).map_err(Error::other), "DFA loading failed")?;
+        self.handle_test_res(dfa.serialize_flat_to(&flat_path), "Serializing the flat DFA failed")?;
+        let mut states = 0;
+        let mut times: [Vec<Duration>; 4] = Default::default();
+        for _ in 0..TRIALS {
+            for (i, path) in [&canonical_path, &flat_path].into_iter().enumerate() {
+                let (state_len, load_time) = self.handle_test_res(Self::time_load(path), "DFA loading failed")?;
+                states = state_len;
+                times[i].push(load_time);
+                times[i + 2].push(self.time_startup(path)?);
+            }
+        }
+        let [canonical_load, flat_load, canonical_startup, flat_startup] = times.map(|times| times.iter().sum::<Duration>() / TRIALS as u32);
+        Some(StartupBenchRes {
+            num: self.num, typ: self.typ.clone(), states,
+            load_times: (canonical_load, flat_load),
+            startup_times: (canonical_startup, flat_startup),
+        })
+    }
+}
+
 fn proj_root() -> PathBuf {
     PathBuf::from(env!("CARGO_MANIFEST_DIR"))
 }
 
+//The benchmarks' own DFA cache, so that clearing it leaves the user's alone
+fn dfa_cache() -> Cache {
+    Cache::at(proj_root().join("serialized-dfa-cache"))
+}
+
 fn read_commands() -> Result<Vec<(String, String)>> {
     let file = File::open(proj_root().join(BENCHMARKS_CSV))?;
     let mut rdr = ReaderBuilder::new()
@@ -213,7 +295,7 @@ fn read_commands() -> Result<Vec<(String, String)>> {
 fn export_speed_results(results: Vec<BenchRes>) -> result::Result<(), Box<dyn error::Error>> {
     let file = File::create(proj_root().join(RESULTS_CSV))?;
     let mut writer = Writer::from_writer(file);
-    writer.write_record(&["Number", "Command", "Raw Time (ms)", "With Monitor Time (ms)", "Type"])?;
+    writer.write_record(["Number", "Command", "Raw Time (ms)", "With Monitor Time (ms)", "Type"])?;
     for res in results {
         writer.write_record(&[
             res.num.to_string(),
@@ -230,7 +312,7 @@ fn export_speed_results(results: Vec<BenchRes>) -> result::Result<(), Box<dyn er
 fn export_comp_results(results: Vec<CompBenchRes>) -> result::Result<(), Box<dyn error::Error>> {
     let file = File::create(proj_root().join(COMP_RESULTS_CSV))?;
     let mut writer = Writer::from_writer(file);
-    writer.write_record(&["Number", "Command", "DFA Time (ms)", "Regex Time (ms)", "Grep Time (ms)", "Ripgrep Time (ms)", "Type"])?;
+    writer.write_record(["Number", "Command", "DFA Time (ms)", "Regex Time (ms)", "Grep Time (ms)", "Ripgrep Time (ms)", "Type"])?;
     for res in results {
         writer.write_record(&[
             res.num.to_string(),
@@ -246,6 +328,44 @@ fn export_comp_results(results: Vec<CompBenchRes>) -> result::Result<(), Box<dyn
     Ok(())
 }
 
+fn export_table_results(results: Vec<TableBenchRes>) -> result::Result<(), Box<dyn error::Error>> {
+    let file = File::create(proj_root().join(TABLE_RESULTS_CSV))?;
+    let mut writer = Writer::from_writer(file);
+    writer.write_record(["Number", "Command", "States", "Byte Classes", "Sparse Table Time (ms)", "Dense Table Time (ms)", "Type"])?;
+    for res in results {
+        writer.write_record(&[
+            res.num.to_string(),
+            res.cmd,
+            res.states.to_string(),
+            res.classes.to_string(),
+            duration_to_ms(&res.sparse_time).to_string(),
+            duration_to_ms(&res.dense_time).to_string(),
+            res.typ, // Automatically quoted if it contains commas
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn export_startup_results(results: Vec<StartupBenchRes>) -> result::Result<(), Box<dyn error::Error>> {
+    let file = File::create(proj_root().join(STARTUP_RESULTS_CSV))?;
+    let mut writer = Writer::from_writer(file);
+    writer.write_record(["Number", "States", "Canonical Load Time (ms)", "Flat Load Time (ms)", "Canonical Startup Time (ms)", "Flat Startup Time (ms)", "Type"])?;
+    for res in results {
+        writer.write_record(&[
+            res.num.to_string(),
+            res.states.to_string(),
+            duration_to_ms(&res.load_times.0).to_string(),
+            duration_to_ms(&res.load_times.1).to_string(),
+            duration_to_ms(&res.startup_times.0).to_string(),
+            duration_to_ms(&res.startup_times.1).to_string(),
+            res.typ, // Automatically quoted if it contains commas
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
 fn duration_to_ms(dur: &Duration) -> f64 {
     dur.as_secs() as f64 * 1000.0 + f64::from(dur.subsec_nanos()) / 1_000_000.0
 }
@@ -269,7 +389,7 @@ fn speed_bench(bmarks: Vec<BenchMark>) {
     let avg_ratio = ratios.iter().sum::<f64>() / ratios.len() as f64;
     println!("\nAverage monitor time / raw time ratio: {:?}\n", avg_ratio);
     //Save Results to CSV
-    export_speed_results(times).expect(format!("Error saving results to {}", RESULTS_CSV).as_str());
+    export_speed_results(times).unwrap_or_else(|e| panic!("Error saving results to {}: {}", RESULTS_CSV, e));
 }
 
 fn comp_bench(bmarks: Vec<BenchMark>) {
@@ -283,7 +403,43 @@ fn comp_bench(bmarks: Vec<BenchMark>) {
             times.push(br);
         }
     }
-    export_comp_results(times).expect(format!("Error saving results to {}", COMP_RESULTS_CSV).as_str());
+    export_comp_results(times).unwrap_or_else(|e| panic!("Error saving results to {}: {}", COMP_RESULTS_CSV, e));
+}
+
+fn table_bench(bmarks: Vec<BenchMark>) {
+    let mut ratios = Vec::new();
+    let mut times = Vec::new();
+    for bench in bmarks {
+        if let Some(br) = bench.table_bench() {
+            println!(
+                "\nBenchmark {} (Command: {}, Type: {}):\n States: {}, Byte Classes: {}\nSparse: {:?}, Dense: {:?}",
+                br.num, br.cmd, br.typ, br.states, br.classes, br.sparse_time, br.dense_time
+            );
+            ratios.push(br.sparse_time.as_secs_f64() / br.dense_time.as_secs_f64());
+            times.push(br);
+        }
+    }
+    let avg_ratio = ratios.iter().sum::<f64>() / ratios.len() as f64;
+    println!("\nAverage sparse time / dense time ratio: {:?}\n", avg_ratio);
+    export_table_results(times).unwrap_or_else(|e| panic!("Error saving results to {}: {}", TABLE_RESULTS_CSV, e));
+}
+
+fn startup_bench(bmarks: Vec<BenchMark>) {
+    let mut ratios = Vec::new();
+    let mut times = Vec::new();
+    for bench in bmarks {
+        if let Some(br) = bench.startup_bench() {
+            println!(
+                "\nBenchmark {} (Type: {}):\n States: {}\nLoad: canonical {:?}, flat {:?}\nStartup: canonical {:?}, flat {:?}",
+                br.num, br.typ, br.states, br.load_times.0, br.load_times.1, br.startup_times.0, br.startup_times.1
+            );
+            ratios.push(br.load_times.0.as_secs_f64() / br.load_times.1.as_secs_f64());
+            times.push(br);
+        }
+    }
+    let avg_ratio = ratios.iter().sum::<f64>() / ratios.len() as f64;
+    println!("\nAverage canonical load time / flat load time ratio: {:?}\n", avg_ratio);
+    export_startup_results(times).unwrap_or_else(|e| panic!("Error saving results to {}: {}", STARTUP_RESULTS_CSV, e));
 }
 
 #[derive(Parser, Debug)]
@@ -292,21 +448,26 @@ struct Args {
     ///Specifies that implementation comparative should be performed instead of standard testing
     #[arg(short, default_value_t = false)]
     comparative_testing: bool,
+    ///Specifies that the sparse and dense DFA transition tables should be benchmarked against each other (in process,
+    ///over each command's captured output) instead of standard testing
+    #[arg(short, default_value_t = false)]
+    table_testing: bool,
+    ///Specifies that loading DFAs serialized in the canonical and flat formats should be benchmarked against each
+    ///other (both in process and as the monitor's startup time) instead of standard testing
+    #[arg(short, default_value_t = false)]
+    startup_testing: bool,
 }
 
 fn main() {
     //OS check - this script should not be run from on a non-linux OS
-    if !(OS == "linux") { 
+    if OS != "linux" { 
         eprintln!("!! This script runs linux commands, so it must be run on a linux OS !!");
         exit(1);
     }
     //Read command-type pairs in from CSV
     let mut commands = read_commands().expect("Error reading commands from CSV");
-    //Create cache directory if it doesn't exist
-    let cache_dir = proj_root().join(DFA_CACHE);
-    if !cache_dir.exists() { create_dir(cache_dir).expect("Failed to create dfa cache dir"); }
-    //Clean DFA's built-in cache of DFAs 
-    Dfa::clean_cache();
+    //Clean the benchmarks' cache of DFAs
+    dfa_cache().clear().expect("Error cleaning the DFA cache");
     //Create benchmarks from commands vector
     let bmarks = commands
         .drain(..)
@@ -315,5 +476,7 @@ fn main() {
     //Run desired benchmarking
     let args = Args::parse();
     if args.comparative_testing { comp_bench(bmarks); }
+    else if args.table_testing { table_bench(bmarks); }
+    else if args.startup_testing { startup_bench(bmarks); }
     else { speed_bench(bmarks); }
 }
\ No newline at end of file